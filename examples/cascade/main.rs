@@ -0,0 +1,209 @@
+//! # Cascade
+//!
+//! This example demonstrates cascading deletes between two controllers with `Owners`. Teams own
+//! their members, and the two are kept in separate tables, reconciled by separate controllers.
+//! Deleting a team deletes its members through the controllers, rather than a database foreign
+//! key, so that each controller can run whatever teardown its objects need on the way out.
+//!
+//! The tables are kept in memory, so the example runs without a database. `Owners` only carries
+//! deletions in memory too, so a restart between a team being removed and its members being
+//! deleted would leave them behind. A real member controller would also sweep members whose team
+//! no longer exists on each resync.
+
+use std::convert::Infallible;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reconciliation::context::ReconcileContext;
+use reconciliation::controller::{Controller, ReconcileOutcome};
+use reconciliation::controller_host::ControllerHost;
+use reconciliation::owners::{OwnerRef, Owners};
+use reconciliation::queue::WorkQueue;
+use reconciliation::store::{RetentionPolicy, Store};
+use reconciliation::testing::InMemoryStore;
+
+/// The kind teams are referred to by in an OwnerRef.
+const TEAM: &str = "team";
+
+#[derive(Debug, Clone)]
+struct Team {
+    id: u64,
+    name: String,
+}
+
+#[derive(Debug, Clone)]
+struct Member {
+    id: u64,
+    name: String,
+    /// The team this member belongs to.
+    owner: OwnerRef,
+}
+
+/// Hard deletes teams once they have been soft deleted for long enough, letting the controllers
+/// of anything they own know as it goes.
+struct TeamController {
+    teams: InMemoryStore<u64, Team>,
+    owners: Owners,
+    retention: RetentionPolicy,
+}
+
+#[async_trait]
+impl Controller for TeamController {
+    type Error = Infallible;
+
+    fn name(&self) -> &str {
+        "team"
+    }
+
+    async fn reconcile(
+        &mut self,
+        _ctx: &mut ReconcileContext,
+    ) -> Result<ReconcileOutcome, Infallible> {
+        // Teams have no status of their own to keep up to date.
+        Ok(ReconcileOutcome::Done)
+    }
+
+    async fn cleanup(&mut self, _ctx: &mut ReconcileContext) -> Result<(), Infallible> {
+        for team in self.teams.all_deleted(&self.retention).await? {
+            println!("Removing team {:?}", team.name);
+            self.owners.deleted(&OwnerRef::new(TEAM, team.id));
+            self.teams.remove(&team.id).await?;
+        }
+        Ok(())
+    }
+
+    fn resync_period(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+}
+
+/// Soft deletes the members of every team that is removed, and hard deletes them once they have
+/// been soft deleted for long enough.
+struct MemberController {
+    members: InMemoryStore<u64, Member>,
+    /// Sent every team that is removed.
+    removed_teams: WorkQueue<OwnerRef>,
+    retention: RetentionPolicy,
+}
+
+#[async_trait]
+impl Controller for MemberController {
+    type Error = Infallible;
+
+    fn name(&self) -> &str {
+        "member"
+    }
+
+    async fn reconcile(
+        &mut self,
+        _ctx: &mut ReconcileContext,
+    ) -> Result<ReconcileOutcome, Infallible> {
+        while let Some(team) = self.removed_teams.try_get() {
+            let members = match self.members.all().await {
+                Ok(members) => members,
+                Err(e) => {
+                    // Retry the team after a backoff. Until it is marked done, the queue would
+                    // never hand it out again.
+                    self.removed_teams.done(&team);
+                    self.removed_teams.add_rate_limited(team);
+                    return Err(e);
+                }
+            };
+            for member in members {
+                if member.owner == team {
+                    println!("Deleting member {:?} along with {}", member.name, team);
+                    self.members.soft_delete(&member.id);
+                }
+            }
+            self.removed_teams.done(&team);
+            self.removed_teams.forget(&team);
+        }
+        Ok(ReconcileOutcome::Done)
+    }
+
+    async fn cleanup(&mut self, _ctx: &mut ReconcileContext) -> Result<(), Infallible> {
+        for member in self.members.all_deleted(&self.retention).await? {
+            println!("Removing member {:?}", member.name);
+            self.members.remove(&member.id).await?;
+        }
+        Ok(())
+    }
+
+    fn resync_period(&self) -> Duration {
+        // Long, since members are only reconciled when a team is removed.
+        Duration::from_secs(60)
+    }
+
+    fn cleanup_period(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let teams = InMemoryStore::new(|team: &Team| team.id);
+    teams.seed(vec![
+        Team {
+            id: 1,
+            name: "red".to_string(),
+        },
+        Team {
+            id: 2,
+            name: "blue".to_string(),
+        },
+    ]);
+    let members = InMemoryStore::new(|member: &Member| member.id);
+    members.seed(vec![
+        Member {
+            id: 1,
+            name: "ada".to_string(),
+            owner: OwnerRef::new(TEAM, 1),
+        },
+        Member {
+            id: 2,
+            name: "grace".to_string(),
+            owner: OwnerRef::new(TEAM, 1),
+        },
+        Member {
+            id: 3,
+            name: "linus".to_string(),
+            owner: OwnerRef::new(TEAM, 2),
+        },
+    ]);
+
+    let owners = Owners::new();
+    let mut host = ControllerHost::new();
+    host.add_controller(Box::new(TeamController {
+        teams: teams.clone(),
+        owners: owners.clone(),
+        retention: RetentionPolicy::new(Duration::from_secs(1)),
+    }))
+    .await
+    .unwrap();
+    let members_trigger = host
+        .add_controller(Box::new(MemberController {
+            members: members.clone(),
+            removed_teams: owners.watch(TEAM),
+            retention: RetentionPolicy::new(Duration::from_secs(1)),
+        }))
+        .await
+        .unwrap();
+    // Delete members as soon as their team is removed, rather than on the next resync.
+    owners.wake(TEAM, members_trigger);
+
+    host.run().await.unwrap();
+
+    println!("Deleting team \"red\"");
+    teams.soft_delete(&1);
+    tokio::time::sleep(Duration::from_secs(5)).await;
+
+    let mut remaining: Vec<String> = members
+        .snapshot()
+        .into_values()
+        .map(|member| member.name)
+        .collect();
+    remaining.sort();
+    println!("Remaining members: {:?}", remaining);
+
+    host.cancel_all().await;
+}
@@ -1,82 +1,209 @@
-use async_trait::async_trait;
+use std::sync::Arc;
+
 use chrono::Utc;
-use reconciliation::controller::Controller;
+use reconciliation::context::ReconcileContext;
+use reconciliation::controller::{Controller, ReconcileOutcome};
+use reconciliation::events::{EventRecorder, EventType, LoggingEventRecorder};
+use reconciliation::store::{RetentionPolicy, Store, Transaction, TransactionalStore};
 
-use crate::{data_access::Hellos, error::Error, models::HelloStatus};
+use crate::{
+    error::Error,
+    models::{Condition, ConditionStatus, Hello, HelloStatus},
+};
 
-/// The controller that will reconcile the hello table and it's related hello_status table.
-pub struct HelloController {
-    hellos: Hellos,
+/// The controller that will reconcile the hello table and it's related hello_status table. It is
+/// generic over the store hellos are kept in, so that it can be run against a fake.
+///
+/// Each reconcile runs in a transaction on the store, so that the statuses it writes are computed
+/// from the hellos as they were read, and a reconcile that fails part way writes none of them.
+///
+/// Only reconciling and cleaning up take any work, so the rest of `Controller` is derived.
+#[derive(Controller)]
+#[controller(
+    error = "Error",
+    name = "hello",
+    resync_period = "self.resync_period",
+    cleanup = "cleanup",
+    validate = "validate",
+    event_recorder = "events",
+    bound = "S: TransactionalStore<Hello, Key = u64>, Error: From<S::Error>"
+)]
+pub struct HelloController<S> {
+    hellos: S,
     resync_period: std::time::Duration,
-    retention_period: chrono::Duration,
+    retention: RetentionPolicy,
+    events: Arc<dyn EventRecorder>,
 }
 
-impl HelloController {
+/// An event to record about a hello: its key, reason, message and type.
+type HelloEvent = (String, &'static str, String, EventType);
+
+impl<S> HelloController<S> {
     pub fn new(
-        hellos: Hellos,
+        hellos: S,
         resync_period: std::time::Duration,
-        retention_period: chrono::Duration,
-    ) -> HelloController {
+        retention: RetentionPolicy,
+    ) -> HelloController<S> {
         HelloController {
             hellos,
             resync_period,
-            retention_period,
+            retention,
+            events: Arc::new(LoggingEventRecorder),
         }
     }
 }
 
-#[async_trait]
-impl Controller for HelloController {
-    type Error = Error;
-
-    // Nothing to do here.
-    async fn initialize(&mut self) -> Result<(), Error> {
+impl<S> HelloController<S>
+where
+    S: TransactionalStore<Hello, Key = u64>,
+    Error: From<S::Error>,
+{
+    fn validate(&self) -> Result<(), Error> {
+        if self.resync_period.is_zero() {
+            return Err(Error::Config("resync period must be positive".to_string()));
+        }
+        if self.retention.default_retention().is_zero() {
+            // Hellos would be hard deleted as soon as they are soft deleted.
+            return Err(Error::Config(
+                "retention period must be positive".to_string(),
+            ));
+        }
         Ok(())
     }
 
-    async fn reconcile(&mut self) -> Result<(), Error> {
-        // Fetch all hellos.
-        for hello in &mut self.hellos.all().await? {
-            let message = format!("Hello, {}!", hello.name);
-            if let Some(status) = &mut hello.status {
-                if status.message == message {
-                    // We don't want to issue unnecessary updates. In a real reconcilier we would
-                    // likely compute much deeper equality checking.
-                    continue;
-                } else {
-                    status.message = message;
-                    status.updated_at = Utc::now();
-                };
-            } else {
-                let status = HelloStatus {
-                    hello_id: hello.id,
-                    created_at: Utc::now(),
-                    updated_at: Utc::now(),
-                    deleted_at: None,
-                    message,
-                };
-                hello.status = Some(status);
-            }
+    async fn reconcile(&mut self, ctx: &mut ReconcileContext) -> Result<ReconcileOutcome, Error> {
+        let tx = self.hellos.begin().await?;
+        let result = self.reconcile_in(&tx, ctx).await;
+        // Commits the statuses written if the pass succeeded, and rolls them back if it failed.
+        let (outcome, events) = tx.finish(result).await?;
 
-            // This is a very simple example, so we insert one at a time. In a real
-            // reconciler we should be batch inserting with prepare and execute.
-            self.hellos.upsert(&hello).await?;
+        // Events are only recorded once the statuses they describe have been committed.
+        for (key, reason, message, event_type) in events {
+            self.events.event(&key, reason, &message, event_type);
         }
 
-        Ok(())
+        Ok(outcome)
     }
 
-    async fn cleanup(&mut self) -> Result<(), Error> {
-        for hello in &mut self.hellos.all_deleted(self.retention_period).await? {
-            // This is a very simple example, so we delete one at a time. In a real
-            // reconciler we should be batch deleting in this step.
-            self.hellos.remove(&hello.id).await?;
+    /// Reconciles every hello within the given transaction, returning the outcome along with the
+    /// events to record once it has been committed.
+    async fn reconcile_in(
+        &self,
+        tx: &S::Transaction,
+        ctx: &mut ReconcileContext,
+    ) -> Result<(ReconcileOutcome, Vec<HelloEvent>), Error> {
+        // Fetch all hellos, and work out which of them need their status updated.
+        let mut changed = Vec::new();
+        let mut events = Vec::new();
+        for mut hello in tx.all().await? {
+            if let Some(status) = &hello.status {
+                if status.observed_generation == hello.generation {
+                    // The status was already computed from this version of the spec, so there's
+                    // nothing to do, without having to compare the status itself.
+                    ctx.record_skipped(1);
+                    continue;
+                }
+            }
+
+            ctx.record_processed(1);
+            let now = Utc::now();
+            // There's nobody to greet without a name, which leaves the hello in an error state
+            // until it is renamed.
+            let (message, ready) = if hello.name.trim().is_empty() {
+                (
+                    String::new(),
+                    Condition {
+                        condition_type: "Ready".to_string(),
+                        status: ConditionStatus::False,
+                        reason: "NameMissing".to_string(),
+                        message: "There is no name to greet.".to_string(),
+                        last_transition_time: now,
+                    },
+                )
+            } else {
+                (
+                    format!("Hello, {}!", hello.name),
+                    Condition {
+                        condition_type: "Ready".to_string(),
+                        status: ConditionStatus::True,
+                        reason: "GreetingComputed".to_string(),
+                        message: "The greeting is up to date.".to_string(),
+                        last_transition_time: now,
+                    },
+                )
+            };
+            events.push(match hello.status {
+                Some(_) => (
+                    hello.id,
+                    "StatusUpdated",
+                    format!("Updated status to {:?}", message),
+                ),
+                None => (
+                    hello.id,
+                    "StatusCreated",
+                    format!("Created status {:?}", message),
+                ),
+            });
+            let hello_id = hello.id;
+            let generation = hello.generation;
+            let status = hello.status.get_or_insert_with(|| HelloStatus {
+                hello_id,
+                created_at: now,
+                updated_at: now,
+                deleted_at: None,
+                version: 0,
+                observed_generation: 0,
+                message: String::new(),
+                conditions: Vec::new(),
+            });
+            status.set_condition(ready);
+            status.message = message;
+            status.updated_at = now;
+            status.observed_generation = generation;
+            changed.push(hello);
         }
 
-        Ok(())
+        // Write every change in one batch, rather than a round trip per hello.
+        let result = tx.upsert_many(&changed).await.map_err(Error::from);
+        let (outcome, conflict) = match result {
+            // Someone else updated a hello since we read it, so requeue to reconcile it again
+            // from its latest state. The rest of the batch was still written.
+            Err(Error::Conflict(id)) => (ReconcileOutcome::Requeue, Some(id)),
+            result => {
+                result?;
+                (ReconcileOutcome::Done, None)
+            }
+        };
+
+        let events = events
+            .into_iter()
+            .map(|(id, reason, message)| {
+                if Some(id) == conflict {
+                    (
+                        id.to_string(),
+                        "Conflict",
+                        "Conflict, requeued".to_string(),
+                        EventType::Warning,
+                    )
+                } else {
+                    (id.to_string(), reason, message, EventType::Normal)
+                }
+            })
+            .collect();
+
+        Ok((outcome, events))
     }
 
-    async fn resync_period(&self) -> std::time::Duration {
-        self.resync_period
+    async fn cleanup(&mut self, _ctx: &mut ReconcileContext) -> Result<(), Error> {
+        let expired: Vec<u64> = self
+            .hellos
+            .all_deleted(&self.retention)
+            .await?
+            .iter()
+            .map(|hello| hello.id)
+            .collect();
+        self.hellos.remove_many(&expired).await?;
+
+        Ok(())
     }
 }
@@ -0,0 +1,103 @@
+//! The repositories `Hello` objects are kept in. MySQL is used unless the `postgres` feature is
+//! enabled. Both implement `Store<Hello>` with the same semantics, including returning
+//! `Error::Conflict` when a status was modified concurrently, so the controller is the same
+//! whichever one it runs against. Both are also a `TransactionalStore<Hello>`, whose
+//! transactions run the same queries on a single database transaction.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, Utc};
+use reconciliation::store::RetentionPolicy;
+
+use crate::models::{Condition, ConditionRow, Hello};
+
+#[cfg(not(feature = "postgres"))]
+mod mysql;
+#[cfg(feature = "postgres")]
+mod postgres;
+
+#[cfg(not(feature = "postgres"))]
+pub use mysql::Hellos;
+#[cfg(feature = "postgres")]
+pub use postgres::Hellos;
+
+/// Attaches each condition to the status of the hello it belongs to. Conditions are fetched in a
+/// query of their own, since joining them in too would repeat each hello once per condition.
+fn attach_conditions(hellos: &mut [Hello], conditions: Vec<ConditionRow>) {
+    let mut by_hello: HashMap<u64, Vec<Condition>> = HashMap::new();
+    for row in conditions {
+        by_hello
+            .entry(row.hello_id)
+            .or_default()
+            .push(Condition::from(row));
+    }
+    for hello in hellos {
+        if let Some(status) = &mut hello.status {
+            status.conditions = by_hello.remove(&hello.id).unwrap_or_default();
+        }
+    }
+}
+
+/// The condition type whose status a deleted hello is classified by for a RetentionPolicy, so
+/// that hellos whose greeting failed can be kept for longer than the rest.
+const RETENTION_CONDITION: &str = "Ready";
+
+/// The cleanup query for a RetentionPolicy, along with the cutoffs to bind to it in order: each
+/// class and the time a hello of that class must have been deleted before, and then the cutoff
+/// for every other hello.
+struct ExpiredQuery {
+    sql: String,
+    classes: Vec<(String, NaiveDateTime)>,
+    default: NaiveDateTime,
+}
+
+impl ExpiredQuery {
+    /// Builds the query selecting every hello that has been deleted for longer than the policy
+    /// keeps hellos of its class. `placeholder` returns the database's placeholder for the bind
+    /// parameter at the given position, counting from 1.
+    fn new(retention: &RetentionPolicy, placeholder: impl Fn(usize) -> String) -> ExpiredQuery {
+        let classes: Vec<(String, NaiveDateTime)> = retention
+            .classes()
+            .map(|(class, age)| (class.to_string(), cutoff(age)))
+            .collect();
+        let default = cutoff(retention.default_retention());
+
+        // How many classes there are varies, so the cutoff is picked with a CASE built to fit.
+        let cutoff_sql = if classes.is_empty() {
+            placeholder(1)
+        } else {
+            let whens: Vec<String> = (0..classes.len())
+                .map(|i| {
+                    format!(
+                        "WHEN {} THEN {}",
+                        placeholder(2 * i + 1),
+                        placeholder(2 * i + 2)
+                    )
+                })
+                .collect();
+            format!(
+                "CASE c.status {} ELSE {} END",
+                whens.join(" "),
+                placeholder(2 * classes.len() + 1)
+            )
+        };
+        let sql = format!(
+            "SELECT h.id, h.created_at, h.updated_at, h.deleted_at, h.version, h.generation, h.name FROM hello h LEFT JOIN hello_status_condition c ON c.hello_id = h.id AND c.condition_type = '{}' WHERE h.deleted_at IS NOT NULL AND h.deleted_at < {}",
+            RETENTION_CONDITION, cutoff_sql
+        );
+
+        ExpiredQuery {
+            sql,
+            classes,
+            default,
+        }
+    }
+}
+
+/// Returns the time a hello must have been deleted before to have been deleted for longer than
+/// the given age.
+fn cutoff(age: Duration) -> NaiveDateTime {
+    let age = chrono::Duration::from_std(age).unwrap_or(chrono::Duration::MAX);
+    (Utc::now() - age).naive_utc()
+}
@@ -0,0 +1,343 @@
+use super::{attach_conditions, ExpiredQuery};
+use crate::error::Error;
+use crate::models::{
+    Condition, ConditionRow, Hello, HelloRow, HelloStatus, HelloStatusRow, HelloWithStatusRow,
+};
+use async_trait::async_trait;
+use reconciliation::store::{RetentionPolicy, Store, Transaction, TransactionalStore};
+use sqlx::error::DatabaseError;
+use sqlx::mysql::MySqlDatabaseError;
+use sqlx::{MySql, MySqlConnection, MySqlPool, Pool};
+use tokio::sync::Mutex;
+
+/// The repository abstraction over access to `Hello` objects, as written into the `hello` table.
+pub struct Hellos {
+    pool: Pool<MySql>,
+}
+
+impl Hellos {
+    pub fn new(pool: Pool<MySql>) -> Hellos {
+        Hellos { pool }
+    }
+
+    /// Connects to the MySQL database at the given url.
+    pub async fn connect(url: &str) -> Result<Hellos, Error> {
+        Ok(Hellos::new(MySqlPool::connect(url).await?))
+    }
+}
+
+#[async_trait]
+impl Store<Hello> for Hellos {
+    type Key = u64;
+    type Error = Error;
+
+    /// Writes the hello's status, guarded by the version it was read at. See `write_status`.
+    async fn upsert(&self, hello: &Hello) -> Result<u64, Error> {
+        let mut tx = self.pool.begin().await?;
+        write_status(&mut tx, hello).await?;
+        tx.commit().await?;
+        Ok(hello.id)
+    }
+
+    /// Writes every hello's status in a single transaction. See `write_statuses`.
+    async fn upsert_many(&self, hellos: &[Hello]) -> Result<Vec<u64>, Error> {
+        let mut tx = self.pool.begin().await?;
+        let conflict = write_statuses(&mut tx, hellos).await?;
+        tx.commit().await?;
+
+        match conflict {
+            Some(id) => Err(Error::Conflict(id)),
+            None => Ok(hellos.iter().map(|hello| hello.id).collect()),
+        }
+    }
+
+    async fn get(&self, key: &u64) -> Result<Option<Hello>, Error> {
+        get(&mut *self.pool.acquire().await?, key).await
+    }
+
+    async fn all(&self) -> Result<Vec<Hello>, Error> {
+        all(&mut *self.pool.acquire().await?).await
+    }
+
+    async fn all_deleted(&self, retention: &RetentionPolicy) -> Result<Vec<Hello>, Error> {
+        all_deleted(&mut *self.pool.acquire().await?, retention).await
+    }
+
+    async fn remove(&self, key: &u64) -> Result<(), Error> {
+        remove(&mut *self.pool.acquire().await?, key).await
+    }
+
+    /// Hard deletes every given spec and its status rows in a single transaction. See
+    /// `remove_all`.
+    async fn remove_many(&self, keys: &[u64]) -> Result<(), Error> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let mut tx = self.pool.begin().await?;
+        remove_all(&mut tx, keys).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TransactionalStore<Hello> for Hellos {
+    type Transaction = HelloTransaction;
+
+    async fn begin(&self) -> Result<HelloTransaction, Error> {
+        Ok(HelloTransaction {
+            tx: Mutex::new(self.pool.begin().await?),
+        })
+    }
+}
+
+/// A transaction on the `hello` tables, begun with `Hellos::begin`. Everything read through it
+/// is read within the transaction, and nothing written through it is seen by anyone else until
+/// it is committed.
+pub struct HelloTransaction {
+    // Store methods take &self, so the transaction's connection is locked for each query.
+    tx: Mutex<sqlx::Transaction<'static, MySql>>,
+}
+
+#[async_trait]
+impl Store<Hello> for HelloTransaction {
+    type Key = u64;
+    type Error = Error;
+
+    /// Writes the hello's status, guarded by the version it was read at. See `write_status`.
+    async fn upsert(&self, hello: &Hello) -> Result<u64, Error> {
+        write_status(&mut **self.tx.lock().await, hello).await?;
+        Ok(hello.id)
+    }
+
+    /// Writes every hello's status. See `write_statuses`.
+    async fn upsert_many(&self, hellos: &[Hello]) -> Result<Vec<u64>, Error> {
+        match write_statuses(&mut **self.tx.lock().await, hellos).await? {
+            Some(id) => Err(Error::Conflict(id)),
+            None => Ok(hellos.iter().map(|hello| hello.id).collect()),
+        }
+    }
+
+    async fn get(&self, key: &u64) -> Result<Option<Hello>, Error> {
+        get(&mut **self.tx.lock().await, key).await
+    }
+
+    async fn all(&self) -> Result<Vec<Hello>, Error> {
+        all(&mut **self.tx.lock().await).await
+    }
+
+    async fn all_deleted(&self, retention: &RetentionPolicy) -> Result<Vec<Hello>, Error> {
+        all_deleted(&mut **self.tx.lock().await, retention).await
+    }
+
+    async fn remove(&self, key: &u64) -> Result<(), Error> {
+        remove(&mut **self.tx.lock().await, key).await
+    }
+
+    async fn remove_many(&self, keys: &[u64]) -> Result<(), Error> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        remove_all(&mut **self.tx.lock().await, keys).await
+    }
+}
+
+#[async_trait]
+impl Transaction for HelloTransaction {
+    type Error = Error;
+
+    async fn commit(self) -> Result<(), Error> {
+        Ok(self.tx.into_inner().commit().await?)
+    }
+
+    async fn rollback(self) -> Result<(), Error> {
+        Ok(self.tx.into_inner().rollback().await?)
+    }
+}
+
+async fn get(conn: &mut MySqlConnection, key: &u64) -> Result<Option<Hello>, Error> {
+    let result: Option<HelloRow> = sqlx::query_as!(
+        HelloRow,
+        "SELECT id, created_at, updated_at, deleted_at, version, generation, name FROM hello WHERE id = ?",
+        key
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    let mut hello = if let Some(row) = result {
+        Hello::from(row)
+    } else {
+        return Ok(None);
+    };
+    let result: Option<HelloStatusRow> = sqlx::query_as!(
+        HelloStatusRow,
+        "SELECT hello_id, created_at, updated_at, deleted_at, version, observed_generation, message FROM hello_status WHERE hello_id = ?",
+        key
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(row) = result {
+        let mut status = HelloStatus::from(row);
+        let conditions: Vec<ConditionRow> = sqlx::query_as!(
+            ConditionRow,
+            "SELECT hello_id, condition_type, status, reason, message, last_transition_time FROM hello_status_condition WHERE hello_id = ?",
+            key
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        status.conditions = conditions.into_iter().map(Condition::from).collect();
+        hello.status = Some(status);
+    };
+
+    Ok(Some(hello))
+}
+
+async fn all(conn: &mut MySqlConnection) -> Result<Vec<Hello>, Error> {
+    // Fetch each hello together with its status in one query, rather than a query per hello.
+    let result: Vec<HelloWithStatusRow> = sqlx::query_as!(
+        HelloWithStatusRow,
+        "SELECT h.id, h.created_at, h.updated_at, h.deleted_at, h.version, h.generation, h.name, s.created_at AS status_created_at, s.updated_at AS status_updated_at, s.deleted_at AS status_deleted_at, s.version AS status_version, s.observed_generation AS status_observed_generation, s.hello_id AS status_hello_id, s.message AS status_message FROM hello h LEFT JOIN hello_status s ON s.hello_id = h.id WHERE h.deleted_at IS NULL",
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let mut hellos: Vec<Hello> = result.into_iter().map(Hello::from).collect();
+
+    let conditions: Vec<ConditionRow> = sqlx::query_as!(
+        ConditionRow,
+        "SELECT c.hello_id, c.condition_type, c.status, c.reason, c.message, c.last_transition_time FROM hello_status_condition c JOIN hello h ON h.id = c.hello_id WHERE h.deleted_at IS NULL",
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    attach_conditions(&mut hellos, conditions);
+
+    Ok(hellos)
+}
+
+/// Classifies each hello by the status of its `Ready` condition. See `ExpiredQuery`.
+async fn all_deleted(
+    conn: &mut MySqlConnection,
+    retention: &RetentionPolicy,
+) -> Result<Vec<Hello>, Error> {
+    let expired = ExpiredQuery::new(retention, |_| "?".to_string());
+    // The number of classes varies, so this can't be checked at compile time like the rest.
+    let mut query = sqlx::query_as::<_, HelloRow>(&expired.sql);
+    for (class, deleted_before) in &expired.classes {
+        query = query.bind(class).bind(deleted_before);
+    }
+    let result = query.bind(expired.default).fetch_all(&mut *conn).await?;
+    let hellos: Vec<Hello> = result.into_iter().map(Hello::from).collect();
+
+    Ok(hellos)
+}
+
+#[allow(unused_must_use)] // This should be idempotent. If it fails we try again anyways.
+async fn remove(conn: &mut MySqlConnection, key: &u64) -> Result<(), Error> {
+    // We have cleanup access, so we should hard delete the spec and any associated status
+    // rows.
+    sqlx::query!("DELETE FROM hello WHERE id = ?", key)
+        .execute(&mut *conn)
+        .await;
+    sqlx::query!("DELETE FROM hello_status WHERE hello_id = ?", key)
+        .execute(&mut *conn)
+        .await;
+    sqlx::query!("DELETE FROM hello_status_condition WHERE hello_id = ?", key)
+        .execute(&mut *conn)
+        .await;
+
+    Ok(())
+}
+
+/// Hard deletes every given spec and its status rows with one statement per table. This should
+/// be run in a transaction.
+async fn remove_all(conn: &mut MySqlConnection, keys: &[u64]) -> Result<(), Error> {
+    let params = vec!["?"; keys.len()].join(", ");
+    let queries = [
+        format!("DELETE FROM hello WHERE id IN ({})", params),
+        format!("DELETE FROM hello_status WHERE hello_id IN ({})", params),
+        format!(
+            "DELETE FROM hello_status_condition WHERE hello_id IN ({})",
+            params
+        ),
+    ];
+
+    for sql in &queries {
+        // The number of keys varies, so these can't be checked at compile time like the rest.
+        let mut query = sqlx::query(sql);
+        for key in keys {
+            query = query.bind(key);
+        }
+        query.execute(&mut *conn).await?;
+    }
+
+    Ok(())
+}
+
+/// Writes every hello's status, each guarded by its version like `write_status`. A status that
+/// conflicts is skipped rather than aborting the whole batch, and the first conflict is returned
+/// once the rest have been written, so this should be run in a transaction.
+async fn write_statuses(
+    conn: &mut MySqlConnection,
+    hellos: &[Hello],
+) -> Result<Option<u64>, Error> {
+    let mut conflict = None;
+    for hello in hellos {
+        match write_status(&mut *conn, hello).await {
+            Err(Error::Conflict(id)) => {
+                conflict.get_or_insert(id);
+            }
+            result => result?,
+        }
+    }
+    Ok(conflict)
+}
+
+/// Writes the hello's status, guarded by the version it was read at. A status with version 0 is
+/// inserted, and any other is only updated if its row is still at that version. Either way, if
+/// another writer got there first this returns `Error::Conflict` rather than overwriting their
+/// update, and the caller should re-read the hello and try again.
+///
+/// The status's conditions are written along with it, so this should be run in a transaction.
+async fn write_status(conn: &mut MySqlConnection, hello: &Hello) -> Result<(), Error> {
+    let status = if let Some(ref s) = hello.status {
+        s
+    } else {
+        return Ok(());
+    };
+    if status.version == 0 {
+        let result = sqlx::query!("INSERT INTO hello_status (hello_id, created_at, updated_at, deleted_at, version, observed_generation, message) VALUES (?, ?, ?, ?, 1, ?, ?)",
+            status.hello_id, status.created_at, status.updated_at, status.deleted_at, status.observed_generation, status.message)
+            .execute(&mut *conn)
+            .await;
+        match result {
+            Err(sqlx::Error::Database(e)) if is_duplicate_key(&*e) => {
+                return Err(Error::Conflict(hello.id))
+            }
+            result => result?,
+        };
+    } else {
+        let result = sqlx::query!("UPDATE hello_status SET updated_at = ?, deleted_at = ?, observed_generation = ?, message = ?, version = version + 1 WHERE hello_id = ? AND version = ?",
+            status.updated_at, status.deleted_at, status.observed_generation, status.message, status.hello_id, status.version)
+            .execute(&mut *conn)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(Error::Conflict(hello.id));
+        }
+    }
+
+    for condition in &status.conditions {
+        sqlx::query!("INSERT INTO hello_status_condition (hello_id, condition_type, status, reason, message, last_transition_time) VALUES (?, ?, ?, ?, ?, ?) ON DUPLICATE KEY UPDATE status = VALUES(status), reason = VALUES(reason), message = VALUES(message), last_transition_time = VALUES(last_transition_time)",
+            status.hello_id, condition.condition_type, condition.status.as_str(), condition.reason, condition.message, condition.last_transition_time)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Returns whether the error is MySQL rejecting a row that would duplicate a unique key.
+fn is_duplicate_key(error: &dyn DatabaseError) -> bool {
+    const ER_DUP_ENTRY: u16 = 1062;
+    error
+        .try_downcast_ref::<MySqlDatabaseError>()
+        .is_some_and(|e| e.number() == ER_DUP_ENTRY)
+}
@@ -0,0 +1,465 @@
+use super::{attach_conditions, ExpiredQuery};
+use crate::error::Error;
+use crate::models::{
+    Condition, ConditionRow, Hello, HelloRow, HelloStatus, HelloStatusRow, HelloWithStatusRow,
+};
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use reconciliation::store::{RetentionPolicy, Store, Transaction, TransactionalStore};
+use sqlx::{PgConnection, PgPool, Pool, Postgres};
+use tokio::sync::Mutex;
+
+/// The repository abstraction over access to `Hello` objects, as written into the `hello` table.
+///
+/// Postgres has no unsigned integers, so ids, versions and generations are stored as `BIGINT`
+/// and converted at the edges. The queries are checked at runtime rather than compile time, as
+/// the offline query data is only prepared against MySQL.
+pub struct Hellos {
+    pool: Pool<Postgres>,
+}
+
+impl Hellos {
+    pub fn new(pool: Pool<Postgres>) -> Hellos {
+        Hellos { pool }
+    }
+
+    /// Connects to the Postgres database at the given url.
+    pub async fn connect(url: &str) -> Result<Hellos, Error> {
+        Ok(Hellos::new(PgPool::connect(url).await?))
+    }
+}
+
+#[async_trait]
+impl Store<Hello> for Hellos {
+    type Key = u64;
+    type Error = Error;
+
+    /// Writes the hello's status, guarded by the version it was read at. See `write_status`.
+    async fn upsert(&self, hello: &Hello) -> Result<u64, Error> {
+        let mut tx = self.pool.begin().await?;
+        write_status(&mut tx, hello).await?;
+        tx.commit().await?;
+        Ok(hello.id)
+    }
+
+    /// Writes every hello's status in a single transaction. See `write_statuses`.
+    async fn upsert_many(&self, hellos: &[Hello]) -> Result<Vec<u64>, Error> {
+        let mut tx = self.pool.begin().await?;
+        let conflict = write_statuses(&mut tx, hellos).await?;
+        tx.commit().await?;
+
+        match conflict {
+            Some(id) => Err(Error::Conflict(id)),
+            None => Ok(hellos.iter().map(|hello| hello.id).collect()),
+        }
+    }
+
+    async fn get(&self, key: &u64) -> Result<Option<Hello>, Error> {
+        get(&mut *self.pool.acquire().await?, key).await
+    }
+
+    async fn all(&self) -> Result<Vec<Hello>, Error> {
+        all(&mut *self.pool.acquire().await?).await
+    }
+
+    async fn all_deleted(&self, retention: &RetentionPolicy) -> Result<Vec<Hello>, Error> {
+        all_deleted(&mut *self.pool.acquire().await?, retention).await
+    }
+
+    async fn remove(&self, key: &u64) -> Result<(), Error> {
+        remove(&mut *self.pool.acquire().await?, key).await
+    }
+
+    /// Hard deletes every given spec and its status rows in a single transaction. See
+    /// `remove_all`.
+    async fn remove_many(&self, keys: &[u64]) -> Result<(), Error> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let mut tx = self.pool.begin().await?;
+        remove_all(&mut tx, keys).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TransactionalStore<Hello> for Hellos {
+    type Transaction = HelloTransaction;
+
+    async fn begin(&self) -> Result<HelloTransaction, Error> {
+        Ok(HelloTransaction {
+            tx: Mutex::new(self.pool.begin().await?),
+        })
+    }
+}
+
+/// A transaction on the `hello` tables, begun with `Hellos::begin`. Everything read through it
+/// is read within the transaction, and nothing written through it is seen by anyone else until
+/// it is committed.
+pub struct HelloTransaction {
+    // Store methods take &self, so the transaction's connection is locked for each query.
+    tx: Mutex<sqlx::Transaction<'static, Postgres>>,
+}
+
+#[async_trait]
+impl Store<Hello> for HelloTransaction {
+    type Key = u64;
+    type Error = Error;
+
+    /// Writes the hello's status, guarded by the version it was read at. See `write_status`.
+    async fn upsert(&self, hello: &Hello) -> Result<u64, Error> {
+        write_status(&mut **self.tx.lock().await, hello).await?;
+        Ok(hello.id)
+    }
+
+    /// Writes every hello's status. See `write_statuses`.
+    async fn upsert_many(&self, hellos: &[Hello]) -> Result<Vec<u64>, Error> {
+        match write_statuses(&mut **self.tx.lock().await, hellos).await? {
+            Some(id) => Err(Error::Conflict(id)),
+            None => Ok(hellos.iter().map(|hello| hello.id).collect()),
+        }
+    }
+
+    async fn get(&self, key: &u64) -> Result<Option<Hello>, Error> {
+        get(&mut **self.tx.lock().await, key).await
+    }
+
+    async fn all(&self) -> Result<Vec<Hello>, Error> {
+        all(&mut **self.tx.lock().await).await
+    }
+
+    async fn all_deleted(&self, retention: &RetentionPolicy) -> Result<Vec<Hello>, Error> {
+        all_deleted(&mut **self.tx.lock().await, retention).await
+    }
+
+    async fn remove(&self, key: &u64) -> Result<(), Error> {
+        remove(&mut **self.tx.lock().await, key).await
+    }
+
+    async fn remove_many(&self, keys: &[u64]) -> Result<(), Error> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        remove_all(&mut **self.tx.lock().await, keys).await
+    }
+}
+
+#[async_trait]
+impl Transaction for HelloTransaction {
+    type Error = Error;
+
+    async fn commit(self) -> Result<(), Error> {
+        Ok(self.tx.into_inner().commit().await?)
+    }
+
+    async fn rollback(self) -> Result<(), Error> {
+        Ok(self.tx.into_inner().rollback().await?)
+    }
+}
+
+async fn get(conn: &mut PgConnection, key: &u64) -> Result<Option<Hello>, Error> {
+    let result: Option<PgHelloRow> = sqlx::query_as(
+        "SELECT id, created_at, updated_at, deleted_at, version, generation, name FROM hello WHERE id = $1",
+    )
+    .bind(*key as i64)
+    .fetch_optional(&mut *conn)
+    .await?;
+    let mut hello = if let Some(row) = result {
+        Hello::from(HelloRow::from(row))
+    } else {
+        return Ok(None);
+    };
+    let result: Option<PgHelloStatusRow> = sqlx::query_as(
+        "SELECT hello_id, created_at, updated_at, deleted_at, version, observed_generation, message FROM hello_status WHERE hello_id = $1",
+    )
+    .bind(*key as i64)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(row) = result {
+        let mut status = HelloStatus::from(HelloStatusRow::from(row));
+        let conditions: Vec<PgConditionRow> = sqlx::query_as(
+            "SELECT hello_id, condition_type, status, reason, message, last_transition_time FROM hello_status_condition WHERE hello_id = $1",
+        )
+        .bind(*key as i64)
+        .fetch_all(&mut *conn)
+        .await?;
+        status.conditions = conditions
+            .into_iter()
+            .map(|row| Condition::from(ConditionRow::from(row)))
+            .collect();
+        hello.status = Some(status);
+    };
+
+    Ok(Some(hello))
+}
+
+async fn all(conn: &mut PgConnection) -> Result<Vec<Hello>, Error> {
+    // Fetch each hello together with its status in one query, rather than a query per hello.
+    let result: Vec<PgHelloWithStatusRow> = sqlx::query_as(
+        "SELECT h.id, h.created_at, h.updated_at, h.deleted_at, h.version, h.generation, h.name, s.created_at AS status_created_at, s.updated_at AS status_updated_at, s.deleted_at AS status_deleted_at, s.version AS status_version, s.observed_generation AS status_observed_generation, s.hello_id AS status_hello_id, s.message AS status_message FROM hello h LEFT JOIN hello_status s ON s.hello_id = h.id WHERE h.deleted_at IS NULL",
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let mut hellos: Vec<Hello> = result
+        .into_iter()
+        .map(|row| Hello::from(HelloWithStatusRow::from(row)))
+        .collect();
+
+    let conditions: Vec<PgConditionRow> = sqlx::query_as(
+        "SELECT c.hello_id, c.condition_type, c.status, c.reason, c.message, c.last_transition_time FROM hello_status_condition c JOIN hello h ON h.id = c.hello_id WHERE h.deleted_at IS NULL",
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    attach_conditions(
+        &mut hellos,
+        conditions.into_iter().map(ConditionRow::from).collect(),
+    );
+
+    Ok(hellos)
+}
+
+/// Classifies each hello by the status of its `Ready` condition. See `ExpiredQuery`.
+async fn all_deleted(
+    conn: &mut PgConnection,
+    retention: &RetentionPolicy,
+) -> Result<Vec<Hello>, Error> {
+    let expired = ExpiredQuery::new(retention, |i| format!("${}", i));
+    let mut query = sqlx::query_as::<_, PgHelloRow>(&expired.sql);
+    for (class, deleted_before) in &expired.classes {
+        query = query.bind(class).bind(deleted_before);
+    }
+    let result = query.bind(expired.default).fetch_all(&mut *conn).await?;
+    let hellos: Vec<Hello> = result
+        .into_iter()
+        .map(|row| Hello::from(HelloRow::from(row)))
+        .collect();
+
+    Ok(hellos)
+}
+
+#[allow(unused_must_use)] // This should be idempotent. If it fails we try again anyways.
+async fn remove(conn: &mut PgConnection, key: &u64) -> Result<(), Error> {
+    // We have cleanup access, so we should hard delete the spec and any associated status
+    // rows.
+    sqlx::query("DELETE FROM hello WHERE id = $1")
+        .bind(*key as i64)
+        .execute(&mut *conn)
+        .await;
+    sqlx::query("DELETE FROM hello_status WHERE hello_id = $1")
+        .bind(*key as i64)
+        .execute(&mut *conn)
+        .await;
+    sqlx::query("DELETE FROM hello_status_condition WHERE hello_id = $1")
+        .bind(*key as i64)
+        .execute(&mut *conn)
+        .await;
+
+    Ok(())
+}
+
+/// Hard deletes every given spec and its status rows with one statement per table. This should
+/// be run in a transaction.
+async fn remove_all(conn: &mut PgConnection, keys: &[u64]) -> Result<(), Error> {
+    // Postgres takes the keys as a single array parameter, however many there are.
+    let keys: Vec<i64> = keys.iter().map(|key| *key as i64).collect();
+    let queries = [
+        "DELETE FROM hello WHERE id = ANY($1)",
+        "DELETE FROM hello_status WHERE hello_id = ANY($1)",
+        "DELETE FROM hello_status_condition WHERE hello_id = ANY($1)",
+    ];
+
+    for sql in &queries {
+        sqlx::query(sql).bind(&keys).execute(&mut *conn).await?;
+    }
+
+    Ok(())
+}
+
+/// Writes every hello's status, each guarded by its version like `write_status`. A status that
+/// conflicts is skipped rather than aborting the whole batch, and the first conflict is returned
+/// once the rest have been written, so this should be run in a transaction.
+async fn write_statuses(conn: &mut PgConnection, hellos: &[Hello]) -> Result<Option<u64>, Error> {
+    let mut conflict = None;
+    for hello in hellos {
+        match write_status(&mut *conn, hello).await {
+            Err(Error::Conflict(id)) => {
+                conflict.get_or_insert(id);
+            }
+            result => result?,
+        }
+    }
+    Ok(conflict)
+}
+
+/// Writes the hello's status, guarded by the version it was read at. A status with version 0 is
+/// inserted, and any other is only updated if its row is still at that version. Either way, if
+/// another writer got there first this returns `Error::Conflict` rather than overwriting their
+/// update, and the caller should re-read the hello and try again.
+///
+/// Postgres aborts the whole transaction on any error, so an insert that loses the race is
+/// detected with `ON CONFLICT DO NOTHING` rather than a duplicate key error, keeping the rest of
+/// a batch alive.
+///
+/// The status's conditions are written along with it, so this should be run in a transaction.
+async fn write_status(conn: &mut PgConnection, hello: &Hello) -> Result<(), Error> {
+    let status = if let Some(ref s) = hello.status {
+        s
+    } else {
+        return Ok(());
+    };
+    let result = if status.version == 0 {
+        sqlx::query("INSERT INTO hello_status (hello_id, created_at, updated_at, deleted_at, version, observed_generation, message) VALUES ($1, $2, $3, $4, 1, $5, $6) ON CONFLICT (hello_id) DO NOTHING")
+            .bind(status.hello_id as i64)
+            .bind(status.created_at.naive_utc())
+            .bind(status.updated_at.naive_utc())
+            .bind(status.deleted_at.map(|time| time.naive_utc()))
+            .bind(status.observed_generation as i64)
+            .bind(&status.message)
+            .execute(&mut *conn)
+            .await?
+    } else {
+        sqlx::query("UPDATE hello_status SET updated_at = $1, deleted_at = $2, observed_generation = $3, message = $4, version = version + 1 WHERE hello_id = $5 AND version = $6")
+            .bind(status.updated_at.naive_utc())
+            .bind(status.deleted_at.map(|time| time.naive_utc()))
+            .bind(status.observed_generation as i64)
+            .bind(&status.message)
+            .bind(status.hello_id as i64)
+            .bind(status.version as i64)
+            .execute(&mut *conn)
+            .await?
+    };
+    if result.rows_affected() == 0 {
+        return Err(Error::Conflict(hello.id));
+    }
+
+    for condition in &status.conditions {
+        sqlx::query("INSERT INTO hello_status_condition (hello_id, condition_type, status, reason, message, last_transition_time) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (hello_id, condition_type) DO UPDATE SET status = EXCLUDED.status, reason = EXCLUDED.reason, message = EXCLUDED.message, last_transition_time = EXCLUDED.last_transition_time")
+            .bind(status.hello_id as i64)
+            .bind(&condition.condition_type)
+            .bind(condition.status.as_str())
+            .bind(&condition.reason)
+            .bind(&condition.message)
+            .bind(condition.last_transition_time.naive_utc())
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    Ok(())
+}
+
+// Super janky. The rows as Postgres returns them, with signed integers, converted into the
+// shared rows once fetched.
+#[derive(sqlx::FromRow)]
+struct PgHelloRow {
+    id: i64,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+    deleted_at: Option<NaiveDateTime>,
+    version: i64,
+    generation: i64,
+    name: String,
+}
+
+impl From<PgHelloRow> for HelloRow {
+    fn from(row: PgHelloRow) -> Self {
+        Self {
+            id: row.id as u64,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+            deleted_at: row.deleted_at,
+            version: row.version as u64,
+            generation: row.generation as u64,
+            name: row.name,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct PgHelloStatusRow {
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+    deleted_at: Option<NaiveDateTime>,
+    version: i64,
+    observed_generation: i64,
+    hello_id: i64,
+    message: String,
+}
+
+impl From<PgHelloStatusRow> for HelloStatusRow {
+    fn from(row: PgHelloStatusRow) -> Self {
+        Self {
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+            deleted_at: row.deleted_at,
+            version: row.version as u64,
+            observed_generation: row.observed_generation as u64,
+            hello_id: row.hello_id as u64,
+            message: row.message,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct PgHelloWithStatusRow {
+    id: i64,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+    deleted_at: Option<NaiveDateTime>,
+    version: i64,
+    generation: i64,
+    name: String,
+
+    status_created_at: Option<NaiveDateTime>,
+    status_updated_at: Option<NaiveDateTime>,
+    status_deleted_at: Option<NaiveDateTime>,
+    status_version: Option<i64>,
+    status_observed_generation: Option<i64>,
+    status_hello_id: Option<i64>,
+    status_message: Option<String>,
+}
+
+impl From<PgHelloWithStatusRow> for HelloWithStatusRow {
+    fn from(row: PgHelloWithStatusRow) -> Self {
+        Self {
+            id: row.id as u64,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+            deleted_at: row.deleted_at,
+            version: row.version as u64,
+            generation: row.generation as u64,
+            name: row.name,
+            status_created_at: row.status_created_at,
+            status_updated_at: row.status_updated_at,
+            status_deleted_at: row.status_deleted_at,
+            status_version: row.status_version.map(|v| v as u64),
+            status_observed_generation: row.status_observed_generation.map(|g| g as u64),
+            status_hello_id: row.status_hello_id.map(|id| id as u64),
+            status_message: row.status_message,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct PgConditionRow {
+    hello_id: i64,
+    condition_type: String,
+    status: String,
+    reason: String,
+    message: String,
+    last_transition_time: NaiveDateTime,
+}
+
+impl From<PgConditionRow> for ConditionRow {
+    fn from(row: PgConditionRow) -> Self {
+        Self {
+            hello_id: row.hello_id as u64,
+            condition_type: row.condition_type,
+            status: row.status,
+            reason: row.reason,
+            message: row.message,
+            last_transition_time: row.last_transition_time,
+        }
+    }
+}
@@ -8,6 +8,14 @@ pub enum Error {
     #[error(transparent)]
     Io(#[from] std::io::Error),
 
+    /// The row being written was modified since it was read, so the write was not applied.
+    #[error("hello {0} was modified concurrently")]
+    Conflict(u64),
+
+    /// The controller was configured in a way it can't run with.
+    #[error("invalid configuration: {0}")]
+    Config(String),
+
     #[error(transparent)]
     JoinError(#[from] tokio::task::JoinError),
 }
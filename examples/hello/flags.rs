@@ -1,3 +1,4 @@
+use reconciliation::logging::LogLevel;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -6,19 +7,17 @@ use structopt::StructOpt;
     about = "reconciles user intent with system state"
 )]
 pub struct Flags {
-    // TODO: Plumb this through and add logging. Probably also change this to actually be log
-    // level enum.
-    /// Log level: error, warn, info, debug, trace
+    /// Log level: error, warn, info, debug, trace. RUST_LOG takes precedence, when set
     #[structopt(long, env = "LOG_LEVEL", default_value = "error")]
-    pub log_level: String,
+    pub log_level: LogLevel,
 
-    // TODO: Probably make all three of these generalist rather than mysql specific.
-    // Also, this contains the username and password as part of the connection string.
+    // TODO: This contains the username and password as part of the connection string.
     // We should probably load those from a file and instead ask for sub-sets of the address and
     // compose it ourselves.
-    /// The full connection string to connect to mysql, including database name
+    /// The full connection string to connect to mysql, or postgres when built with the postgres
+    /// feature, including database name
     #[structopt(long, env = "DATABASE_URL")]
-    pub mysql_url: String,
+    pub database_url: String,
 
     /// Reconcile controllers with this period even if no events occur
     #[structopt(long, env = "RESYNC_PERIOD_SECONDS", default_value = "5")]
@@ -26,6 +25,6 @@ pub struct Flags {
 
     /// Provides a default retention period, by which we will clean up soft deleted data if it has
     /// been around beyond the number of days specified
-    #[structopt(long, env = "RESYNC_PERIOD_SECONDS", default_value = "3")]
-    pub retention_period_days: u64,
+    #[structopt(long, env = "RETENTION_PERIOD_DAYS", default_value = "3")]
+    pub retention_period_days: u32,
 }
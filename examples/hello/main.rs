@@ -8,9 +8,10 @@
 
 use chrono::Duration;
 use reconciliation::controller_host::ControllerHost;
-use sqlx::MySqlPool;
+use reconciliation::logging;
+use reconciliation::signals;
+use reconciliation::store::RetentionPolicy;
 use structopt::StructOpt;
-use tokio::signal::unix::{signal, SignalKind};
 
 mod controller;
 mod data_access;
@@ -22,36 +23,37 @@ use controller::HelloController;
 use data_access::Hellos;
 use error::Error;
 use flags::Flags;
+use models::ConditionStatus;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     let flags = Flags::from_args();
-    let pool = MySqlPool::connect(&flags.mysql_url).await?;
-    let hellos = Hellos::new(pool);
+    // Nothing else has installed a subscriber yet, so this can't fail.
+    logging::init_tracing(flags.log_level).unwrap();
+    let hellos = Hellos::connect(&flags.database_url).await?;
 
+    let day = Duration::days(1).to_std().unwrap();
+    let retention = day * flags.retention_period_days;
     let mut host = ControllerHost::new();
+    // Adding the controller validates it, so a zero period given on the command line is rejected
+    // here.
     host.add_controller(Box::new(HelloController::new(
         hellos,
-        // We set these both short to make the demo app very responsive.
-        Duration::seconds(5).to_std().unwrap(),
-        Duration::seconds(10),
+        std::time::Duration::from_secs(flags.resync_period_seconds),
+        // Hellos that were deleted while they had no name to greet are kept around for twice as
+        // long, in case someone wants to look into what went wrong.
+        RetentionPolicy::new(retention).with_class(ConditionStatus::False.as_str(), retention * 2),
     )))
-    .await;
-
-    host.run().await;
-
-    let mut sigterm = signal(SignalKind::terminate())?;
-    let mut sigint = signal(SignalKind::interrupt())?;
-    tokio::select! {
-        _ = sigterm.recv() => {
-            eprintln!("received sigterm");
-        },
-        _ = sigint.recv() => {
-            eprintln!("received sigint");
-        },
-    }
-
-    host.cancel_all().await;
+    .await?;
+
+    // The host was only just made, so it can't be running yet.
+    host.run().await.unwrap();
+
+    signals::shutdown().await?;
+
+    // Leave some headroom under the usual 30 second grace period before we would be killed.
+    host.cancel_all_timeout(Duration::seconds(25).to_std().unwrap())
+        .await;
 
     Ok(())
 }
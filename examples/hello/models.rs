@@ -4,12 +4,18 @@ use chrono::{DateTime, NaiveDateTime, Utc};
 ///
 /// Models are usually defined in the dal crate, but this is here so we don't pollute that crate
 /// with examples.
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct Hello {
     pub id: u64,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub deleted_at: Option<DateTime<Utc>>,
+    /// Bumped on every write to the spec.
+    pub version: u64,
+    /// Bumped only when the spec itself changes, so that a status can record which spec it was
+    /// computed from.
+    pub generation: u64,
 
     pub name: String,
     pub status: Option<HelloStatus>,
@@ -23,6 +29,8 @@ pub struct HelloRow {
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
     pub deleted_at: Option<NaiveDateTime>,
+    pub version: u64,
+    pub generation: u64,
 
     pub name: String,
 }
@@ -31,9 +39,13 @@ impl From<HelloRow> for Hello {
     fn from(row: HelloRow) -> Self {
         Self {
             id: row.id,
-            created_at: DateTime::from_utc(row.created_at, Utc),
-            updated_at: DateTime::from_utc(row.updated_at, Utc),
-            deleted_at: row.deleted_at.map(|time| DateTime::from_utc(time, Utc)),
+            created_at: DateTime::from_naive_utc_and_offset(row.created_at, Utc),
+            updated_at: DateTime::from_naive_utc_and_offset(row.updated_at, Utc),
+            deleted_at: row
+                .deleted_at
+                .map(|time| DateTime::from_naive_utc_and_offset(time, Utc)),
+            version: row.version,
+            generation: row.generation,
             name: row.name,
             status: None,
         }
@@ -49,9 +61,52 @@ pub struct HelloStatus {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub deleted_at: Option<DateTime<Utc>>,
+    /// The version of the row this status was read from, which an upsert expects to still be
+    /// current. A status that hasn't been written yet has version 0.
+    pub version: u64,
+    /// The generation of the spec this status was last computed from. Once it matches the
+    /// spec's generation, there's nothing left to reconcile.
+    pub observed_generation: u64,
 
     pub hello_id: u64,
     pub message: String,
+    /// Held in the `hello_status_condition` table, one row per condition type.
+    pub conditions: Vec<Condition>,
+}
+
+impl HelloStatus {
+    /// Sets the given condition, replacing any existing condition of the same type. The existing
+    /// condition's `last_transition_time` is kept unless its status changed, so that it records
+    /// when the condition last transitioned rather than when it was last written. Returns whether
+    /// anything about the condition changed.
+    pub fn set_condition(&mut self, mut condition: Condition) -> bool {
+        match self
+            .conditions
+            .iter_mut()
+            .find(|c| c.condition_type == condition.condition_type)
+        {
+            Some(existing) => {
+                if existing.status == condition.status {
+                    condition.last_transition_time = existing.last_transition_time;
+                }
+                let changed = *existing != condition;
+                *existing = condition;
+                changed
+            }
+            None => {
+                self.conditions.push(condition);
+                true
+            }
+        }
+    }
+
+    /// Returns the condition of the given type, if it has been set.
+    #[allow(dead_code)]
+    pub fn condition(&self, condition_type: &str) -> Option<&Condition> {
+        self.conditions
+            .iter()
+            .find(|c| c.condition_type == condition_type)
+    }
 }
 
 // Super janky. Needed because sqlx derive is very limited and implementing FromRow ourselves is
@@ -61,6 +116,8 @@ pub struct HelloStatusRow {
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
     pub deleted_at: Option<NaiveDateTime>,
+    pub version: u64,
+    pub observed_generation: u64,
 
     pub hello_id: u64,
     pub message: String,
@@ -69,11 +126,155 @@ pub struct HelloStatusRow {
 impl From<HelloStatusRow> for HelloStatus {
     fn from(row: HelloStatusRow) -> Self {
         Self {
-            created_at: DateTime::from_utc(row.created_at, Utc),
-            updated_at: DateTime::from_utc(row.updated_at, Utc),
-            deleted_at: row.deleted_at.map(|time| DateTime::from_utc(time, Utc)),
+            created_at: DateTime::from_naive_utc_and_offset(row.created_at, Utc),
+            updated_at: DateTime::from_naive_utc_and_offset(row.updated_at, Utc),
+            deleted_at: row
+                .deleted_at
+                .map(|time| DateTime::from_naive_utc_and_offset(time, Utc)),
+            version: row.version,
+            observed_generation: row.observed_generation,
             hello_id: row.hello_id,
             message: row.message,
+            conditions: Vec::new(),
+        }
+    }
+}
+
+// Super janky. Every column of a hello joined with its hello_status, if it has one, so that they
+// can be fetched in one query. The status columns are all null when there's no status row.
+#[derive(sqlx::FromRow)]
+pub struct HelloWithStatusRow {
+    pub id: u64,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub deleted_at: Option<NaiveDateTime>,
+    pub version: u64,
+    pub generation: u64,
+    pub name: String,
+
+    pub status_created_at: Option<NaiveDateTime>,
+    pub status_updated_at: Option<NaiveDateTime>,
+    pub status_deleted_at: Option<NaiveDateTime>,
+    pub status_version: Option<u64>,
+    pub status_observed_generation: Option<u64>,
+    pub status_hello_id: Option<u64>,
+    pub status_message: Option<String>,
+}
+
+impl From<HelloWithStatusRow> for Hello {
+    fn from(row: HelloWithStatusRow) -> Self {
+        let status = match (
+            row.status_created_at,
+            row.status_updated_at,
+            row.status_version,
+            row.status_observed_generation,
+            row.status_hello_id,
+            row.status_message,
+        ) {
+            (
+                Some(created_at),
+                Some(updated_at),
+                Some(version),
+                Some(observed_generation),
+                Some(hello_id),
+                Some(message),
+            ) => Some(HelloStatus {
+                created_at: DateTime::from_naive_utc_and_offset(created_at, Utc),
+                updated_at: DateTime::from_naive_utc_and_offset(updated_at, Utc),
+                deleted_at: row
+                    .status_deleted_at
+                    .map(|time| DateTime::from_naive_utc_and_offset(time, Utc)),
+                version,
+                observed_generation,
+                hello_id,
+                message,
+                conditions: Vec::new(),
+            }),
+            _ => None,
+        };
+        Self {
+            id: row.id,
+            created_at: DateTime::from_naive_utc_and_offset(row.created_at, Utc),
+            updated_at: DateTime::from_naive_utc_and_offset(row.updated_at, Utc),
+            deleted_at: row
+                .deleted_at
+                .map(|time| DateTime::from_naive_utc_and_offset(time, Utc)),
+            version: row.version,
+            generation: row.generation,
+            name: row.name,
+            status,
+        }
+    }
+}
+
+/// Condition is a single aspect of a hello's observed state, such as whether it is `Ready`,
+/// `Progressing` or `Degraded`, and holds a row from the `hello_status_condition` table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    /// The aspect of the hello's state this condition describes.
+    pub condition_type: String,
+    pub status: ConditionStatus,
+    /// A short, machine readable explanation of why the condition has its status.
+    pub reason: String,
+    /// A human readable explanation of why the condition has its status.
+    pub message: String,
+    /// When the condition's status last changed.
+    pub last_transition_time: DateTime<Utc>,
+}
+
+/// Whether a Condition currently holds.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConditionStatus {
+    True,
+    False,
+    Unknown,
+}
+
+impl ConditionStatus {
+    /// Returns the status as it is stored in the `hello_status_condition` table.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConditionStatus::True => "True",
+            ConditionStatus::False => "False",
+            ConditionStatus::Unknown => "Unknown",
+        }
+    }
+}
+
+impl From<&str> for ConditionStatus {
+    fn from(status: &str) -> Self {
+        match status {
+            "True" => ConditionStatus::True,
+            "False" => ConditionStatus::False,
+            _ => ConditionStatus::Unknown,
+        }
+    }
+}
+
+// Super janky. Needed because sqlx derive is very limited and implementing FromRow ourselves is
+// herrendously complicated.
+#[derive(sqlx::FromRow)]
+pub struct ConditionRow {
+    pub hello_id: u64,
+    pub condition_type: String,
+    pub status: String,
+    pub reason: String,
+    pub message: String,
+    pub last_transition_time: NaiveDateTime,
+}
+
+impl From<ConditionRow> for Condition {
+    fn from(row: ConditionRow) -> Self {
+        Self {
+            condition_type: row.condition_type,
+            status: ConditionStatus::from(row.status.as_str()),
+            reason: row.reason,
+            message: row.message,
+            last_transition_time: DateTime::from_naive_utc_and_offset(
+                row.last_transition_time,
+                Utc,
+            ),
         }
     }
 }
@@ -0,0 +1,47 @@
+CREATE TABLE IF NOT EXISTS hello (
+	id BIGSERIAL PRIMARY KEY,
+	created_at TIMESTAMP NOT NULL,
+	updated_at TIMESTAMP NOT NULL,
+	deleted_at TIMESTAMP DEFAULT NULL,
+	version BIGINT NOT NULL DEFAULT 0,
+	generation BIGINT NOT NULL DEFAULT 1,
+	name VARCHAR(256) NOT NULL
+);
+
+-- Bump the generation whenever the spec itself changes, whoever changes it, so that reconcilers
+-- can tell which specs still need reconciling.
+CREATE OR REPLACE FUNCTION hello_generation() RETURNS TRIGGER AS $$
+BEGIN
+	IF NEW.name <> OLD.name THEN
+		NEW.generation := OLD.generation + 1;
+	ELSE
+		NEW.generation := OLD.generation;
+	END IF;
+	RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS hello_generation ON hello;
+CREATE TRIGGER hello_generation BEFORE UPDATE ON hello FOR EACH ROW
+	EXECUTE PROCEDURE hello_generation();
+
+CREATE TABLE IF NOT EXISTS hello_status (
+	id BIGSERIAL PRIMARY KEY,
+	hello_id BIGINT NOT NULL UNIQUE,
+	created_at TIMESTAMP NOT NULL,
+	updated_at TIMESTAMP NOT NULL,
+	deleted_at TIMESTAMP DEFAULT NULL,
+	version BIGINT NOT NULL DEFAULT 0,
+	observed_generation BIGINT NOT NULL DEFAULT 0,
+	message VARCHAR(256) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS hello_status_condition (
+	hello_id BIGINT NOT NULL,
+	condition_type VARCHAR(64) NOT NULL,
+	status VARCHAR(16) NOT NULL,
+	reason VARCHAR(256) NOT NULL,
+	message VARCHAR(256) NOT NULL,
+	last_transition_time TIMESTAMP NOT NULL,
+	PRIMARY KEY (hello_id, condition_type)
+);
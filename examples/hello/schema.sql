@@ -3,16 +3,36 @@ CREATE TABLE IF NOT EXISTS hello (
 	created_at datetime(6) NOT NULL,
 	updated_at datetime(6) NOT NULL,
 	deleted_at datetime(6) DEFAULT NULL,
+	version BIGINT UNSIGNED NOT NULL DEFAULT 0,
+	generation BIGINT UNSIGNED NOT NULL DEFAULT 1,
 	name VARCHAR(256) NOT NULL,
 	PRIMARY KEY (id)
 );
 
+-- Bump the generation whenever the spec itself changes, whoever changes it, so that reconcilers
+-- can tell which specs still need reconciling.
+CREATE TRIGGER IF NOT EXISTS hello_generation BEFORE UPDATE ON hello FOR EACH ROW
+	SET NEW.generation = IF(NEW.name <> OLD.name, OLD.generation + 1, OLD.generation);
+
 CREATE TABLE IF NOT EXISTS hello_status (
 	id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
 	hello_id BIGINT UNSIGNED NOT NULL,
 	created_at datetime(6) NOT NULL,
 	updated_at datetime(6) NOT NULL,
 	deleted_at datetime(6) DEFAULT NULL,
+	version BIGINT UNSIGNED NOT NULL DEFAULT 0,
+	observed_generation BIGINT UNSIGNED NOT NULL DEFAULT 0,
 	message VARCHAR(256) NOT NULL,
-	PRIMARY KEY (id)
+	PRIMARY KEY (id),
+	UNIQUE KEY (hello_id)
+);
+
+CREATE TABLE IF NOT EXISTS hello_status_condition (
+	hello_id BIGINT UNSIGNED NOT NULL,
+	condition_type VARCHAR(64) NOT NULL,
+	status VARCHAR(16) NOT NULL,
+	reason VARCHAR(256) NOT NULL,
+	message VARCHAR(256) NOT NULL,
+	last_transition_time datetime(6) NOT NULL,
+	PRIMARY KEY (hello_id, condition_type)
 );
@@ -0,0 +1,262 @@
+//! Derives `reconciliation::controller::Controller`, so that simple controllers don't have to
+//! write out every method of the trait. Enable the `derive` feature of the reconciliation crate
+//! and use it from there, rather than depending on this crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::punctuated::Punctuated;
+use syn::{
+    parse_macro_input, DeriveInput, Error, Expr, Ident, Lit, LitStr, Meta, NestedMeta, Token, Type,
+    WherePredicate,
+};
+
+/// Implements `Controller` for a struct, configured by a `#[controller(...)]` attribute on it.
+/// Every value is given as a string, holding the Rust it stands for:
+///
+/// - `error`: the controller's error type. Required.
+/// - `resync_period`: an expression for the resync period, which can read fields through `self`,
+///   like `"self.resync_period"`. Required.
+/// - `cleanup_period`: an expression for the cleanup period. Defaults to the resync period.
+/// - `reconcile`: the name of an inherent
+///   `async fn(&mut self, &mut ReconcileContext) -> Result<ReconcileOutcome, E>` method to
+///   reconcile with. Defaults to `"reconcile"`.
+/// - `initialize`: the name of an inherent `async fn(&mut self) -> Result<(), E>` method to
+///   initialize with. Defaults to doing nothing.
+/// - `cleanup`: the name of an inherent
+///   `async fn(&mut self, &mut ReconcileContext) -> Result<(), E>` method to clean up with.
+///   Without one, the controller is derived to have no cleanup, with `has_cleanup` returning
+///   `false`.
+/// - `validate`: the name of an inherent `fn(&self) -> Result<(), E>` method to validate the
+///   controller's configuration with. Defaults to accepting any.
+/// - `name`: the controller's name. Defaults to its type name.
+/// - `phase`: an expression for the controller's startup phase. Defaults to 0.
+/// - `event_recorder`: the name of an `Arc<dyn EventRecorder>` field to store the recorder the
+///   controller is handed in. By default it is dropped.
+/// - `bound`: extra where clause predicates the impl needs, such as bounds on the struct's type
+///   parameters that its methods rely on, like `"S: Store<Hello>"`.
+///
+/// Each method named must be inherent to the struct: naming one that isn't is a compile error,
+/// rather than a call to the trait method being derived.
+#[proc_macro_derive(Controller, attributes(controller))]
+pub fn derive_controller(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// The options given in a `#[controller(...)]` attribute.
+#[derive(Default)]
+struct Options {
+    error: Option<Type>,
+    resync_period: Option<Expr>,
+    cleanup_period: Option<Expr>,
+    reconcile: Option<Ident>,
+    initialize: Option<Ident>,
+    cleanup: Option<Ident>,
+    validate: Option<Ident>,
+    name: Option<LitStr>,
+    phase: Option<Expr>,
+    event_recorder: Option<Ident>,
+    bound: Vec<WherePredicate>,
+}
+
+impl Options {
+    /// Parses the options out of every `#[controller(...)]` attribute on the input.
+    fn parse(input: &DeriveInput) -> Result<Options, Error> {
+        let mut options = Options::default();
+        for attr in input.attrs.iter().filter(|a| a.path.is_ident("controller")) {
+            let list = match attr.parse_meta()? {
+                Meta::List(list) => list,
+                meta => return Err(Error::new_spanned(meta, "expected #[controller(...)]")),
+            };
+            for nested in list.nested {
+                let pair = match nested {
+                    NestedMeta::Meta(Meta::NameValue(pair)) => pair,
+                    nested => {
+                        return Err(Error::new_spanned(nested, "expected `key = \"value\"`"));
+                    }
+                };
+                let value = match &pair.lit {
+                    Lit::Str(value) => value.clone(),
+                    lit => return Err(Error::new_spanned(lit, "expected a string")),
+                };
+                let key = pair.path.get_ident().map(|key| key.to_string());
+                match key.as_deref() {
+                    Some("error") => options.error = Some(value.parse()?),
+                    Some("resync_period") => options.resync_period = Some(value.parse()?),
+                    Some("cleanup_period") => options.cleanup_period = Some(value.parse()?),
+                    Some("reconcile") => options.reconcile = Some(value.parse()?),
+                    Some("initialize") => options.initialize = Some(value.parse()?),
+                    Some("cleanup") => options.cleanup = Some(value.parse()?),
+                    Some("validate") => options.validate = Some(value.parse()?),
+                    Some("name") => options.name = Some(value),
+                    Some("phase") => options.phase = Some(value.parse()?),
+                    Some("event_recorder") => options.event_recorder = Some(value.parse()?),
+                    Some("bound") => {
+                        let bound = value.parse_with(
+                            Punctuated::<WherePredicate, Token![,]>::parse_terminated,
+                        )?;
+                        options.bound.extend(bound);
+                    }
+                    _ => return Err(Error::new_spanned(pair.path, "unknown controller option")),
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn expand(mut input: DeriveInput) -> Result<TokenStream2, Error> {
+    let options = Options::parse(&input)?;
+    let error = options.error.ok_or_else(|| {
+        Error::new_spanned(&input.ident, "missing #[controller(error = \"...\")]")
+    })?;
+    let resync_period = options.resync_period.ok_or_else(|| {
+        Error::new_spanned(
+            &input.ident,
+            "missing #[controller(resync_period = \"...\")]",
+        )
+    })?;
+
+    let ident = &input.ident;
+    if !options.bound.is_empty() {
+        input
+            .generics
+            .make_where_clause()
+            .predicates
+            .extend(options.bound);
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let reconcile = options
+        .reconcile
+        .unwrap_or_else(|| Ident::new("reconcile", proc_macro2::Span::call_site()));
+    let reconcile_guard = inherent_only(&reconcile);
+    let initialize = options.initialize.map(|method| {
+        let guard = inherent_only(&method);
+        quote! {
+            async fn initialize(&mut self) -> ::std::result::Result<(), Self::Error> {
+                #guard
+                Self::#method(self).await
+            }
+        }
+    });
+    let has_cleanup = options.cleanup.is_none().then(|| {
+        quote! {
+            fn has_cleanup(&self) -> bool {
+                false
+            }
+        }
+    });
+    let cleanup = options.cleanup.map(|method| {
+        let guard = inherent_only(&method);
+        quote! {
+            async fn cleanup(
+                &mut self,
+                ctx: &mut ::reconciliation::context::ReconcileContext,
+            ) -> ::std::result::Result<(), Self::Error> {
+                #guard
+                Self::#method(self, ctx).await
+            }
+        }
+    });
+    let validate = options.validate.map(|method| {
+        let guard = inherent_only(&method);
+        quote! {
+            fn validate(&self) -> ::std::result::Result<(), Self::Error> {
+                #guard
+                Self::#method(self)
+            }
+        }
+    });
+    let name = options.name.map(|name| {
+        quote! {
+            fn name(&self) -> &str {
+                #name
+            }
+        }
+    });
+    let phase = options.phase.map(|phase| {
+        quote! {
+            fn phase(&self) -> u32 {
+                #phase
+            }
+        }
+    });
+    let cleanup_period = options.cleanup_period.map(|cleanup_period| {
+        quote! {
+            fn cleanup_period(&self) -> ::std::time::Duration {
+                #cleanup_period
+            }
+        }
+    });
+    let event_recorder = options.event_recorder.map(|field| {
+        quote! {
+            fn set_event_recorder(
+                &mut self,
+                events: ::std::sync::Arc<dyn ::reconciliation::events::EventRecorder>,
+            ) {
+                self.#field = events;
+            }
+        }
+    });
+
+    Ok(quote! {
+        #[::reconciliation::__private::async_trait]
+        impl #impl_generics ::reconciliation::controller::Controller for #ident #ty_generics
+        #where_clause
+        {
+            type Error = #error;
+
+            #name
+
+            #phase
+
+            #initialize
+
+            async fn reconcile(
+                &mut self,
+                ctx: &mut ::reconciliation::context::ReconcileContext,
+            ) -> ::std::result::Result<
+                ::reconciliation::controller::ReconcileOutcome,
+                Self::Error,
+            > {
+                #reconcile_guard
+                Self::#reconcile(self, ctx).await
+            }
+
+            #cleanup
+
+            fn resync_period(&self) -> ::std::time::Duration {
+                #resync_period
+            }
+
+            #cleanup_period
+
+            #validate
+
+            #has_cleanup
+
+            #event_recorder
+        }
+    })
+}
+
+/// Returns an item to put in the body of a generated method delegating to the inherent method
+/// given, so that the call cannot resolve to anything else. The methods delegated to usually share
+/// their name with the Controller method being generated, which `Self::method` would otherwise
+/// resolve to, recursing forever, when the struct has no inherent method by that name. With this
+/// trait in scope as well that call is ambiguous, and fails to compile, while an inherent method
+/// still takes precedence over both.
+fn inherent_only(method: &Ident) -> TokenStream2 {
+    quote! {
+        #[allow(dead_code)]
+        trait InherentOnly {
+            fn #method(&self) {}
+        }
+        impl<T: ?Sized> InherentOnly for T {}
+    }
+}
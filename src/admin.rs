@@ -0,0 +1,350 @@
+//! A minimal admin HTTP endpoint for checking on a running ControllerHost, with `curl` or an
+//! orchestrator's probes. Enabled by the `admin` feature, and served with
+//! `ControllerHost::serve_admin`.
+//!
+//! It serves:
+//!
+//! - `/healthz`: 200 unless a control loop has stopped on its own for good, otherwise 503.
+//! - `/readyz`: 200 while every controller is ready and healthy, otherwise 503, so that nothing
+//!   is routed to a host whose controllers haven't yet caught up since starting.
+//! - `/status`: a JSON array with each controller's `ControllerHealth`.
+//! - `/metrics`: the measurements of a `PrometheusMetrics`, in the Prometheus text format, if the
+//!   endpoint was given one.
+//!
+//! A failing probe's body lists each controller that failed it, with why. See `ProbeReport`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tracing::{debug, warn};
+
+use crate::context::ReconcileStats;
+use crate::controller::SourceChain;
+use crate::health::{ControllerHealth, Health, HealthRegistry, ProbeReport};
+use crate::metrics::ControllerMetrics;
+
+/// The most a request may take up, which is plenty for a request line and headers.
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+/// How long a client has to send its request and read the response before the connection is
+/// dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait before accepting again after failing to accept a connection. Failures like
+/// running out of file descriptors persist for a while, and retrying right away would spin.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// A ControllerMetrics that keeps a running count and total duration of every call, by controller
+/// and whether it succeeded, along with a running count of the items reconciles processed and
+/// skipped, to be scraped from the admin endpoint's `/metrics`. Share one between
+/// `ExecutorConfig::metrics` and `ControllerHost::serve_admin`.
+#[derive(Debug, Default)]
+pub struct PrometheusMetrics {
+    calls: Mutex<BTreeMap<(String, &'static str), CallStats>>,
+    items: Mutex<BTreeMap<String, ReconcileStats>>,
+}
+
+/// The measurements of one kind of call into one controller.
+#[derive(Debug, Default)]
+struct CallStats {
+    successes: u64,
+    failures: u64,
+    seconds: f64,
+}
+
+impl PrometheusMetrics {
+    /// Create a new PrometheusMetrics with nothing recorded.
+    pub fn new() -> PrometheusMetrics {
+        PrometheusMetrics::default()
+    }
+
+    fn record(&self, name: &str, call: &'static str, duration: Duration, result: Result<(), ()>) {
+        let mut calls = self.calls.lock().unwrap();
+        let stats = calls.entry((name.to_string(), call)).or_default();
+        match result {
+            Ok(()) => stats.successes += 1,
+            Err(()) => stats.failures += 1,
+        }
+        stats.seconds += duration.as_secs_f64();
+    }
+
+    /// Renders every measurement recorded so far in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let calls = self.calls.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP reconciliation_calls_total Calls made into each controller.\n");
+        out.push_str("# TYPE reconciliation_calls_total counter\n");
+        for ((name, call), stats) in calls.iter() {
+            for (result, count) in [("success", stats.successes), ("failure", stats.failures)] {
+                let _ = writeln!(
+                    out,
+                    "reconciliation_calls_total{{controller=\"{}\",call=\"{}\",result=\"{}\"}} {}",
+                    escape(name),
+                    call,
+                    result,
+                    count
+                );
+            }
+        }
+
+        out.push_str(
+            "# HELP reconciliation_call_duration_seconds How long calls into each controller took.\n",
+        );
+        out.push_str("# TYPE reconciliation_call_duration_seconds summary\n");
+        for ((name, call), stats) in calls.iter() {
+            let labels = format!("controller=\"{}\",call=\"{}\"", escape(name), call);
+            let _ = writeln!(
+                out,
+                "reconciliation_call_duration_seconds_sum{{{}}} {}",
+                labels, stats.seconds
+            );
+            let _ = writeln!(
+                out,
+                "reconciliation_call_duration_seconds_count{{{}}} {}",
+                labels,
+                stats.successes + stats.failures
+            );
+        }
+        drop(calls);
+
+        let items = self.items.lock().unwrap();
+        out.push_str("# HELP reconciliation_items_total Items reconciles processed and skipped.\n");
+        out.push_str("# TYPE reconciliation_items_total counter\n");
+        for (name, stats) in items.iter() {
+            for (outcome, count) in [("processed", stats.processed), ("skipped", stats.skipped)] {
+                let _ = writeln!(
+                    out,
+                    "reconciliation_items_total{{controller=\"{}\",outcome=\"{}\"}} {}",
+                    escape(name),
+                    outcome,
+                    count
+                );
+            }
+        }
+        out
+    }
+}
+
+impl ControllerMetrics for PrometheusMetrics {
+    fn record_reconcile(&self, name: &str, duration: Duration, result: Result<(), ()>) {
+        self.record(name, "reconcile", duration, result);
+    }
+
+    fn record_cleanup(&self, name: &str, duration: Duration, result: Result<(), ()>) {
+        self.record(name, "cleanup", duration, result);
+    }
+
+    fn record_reconcile_items(&self, name: &str, stats: ReconcileStats) {
+        let mut items = self.items.lock().unwrap();
+        let total = items.entry(name.to_string()).or_default();
+        total.processed += stats.processed;
+        total.skipped += stats.skipped;
+    }
+}
+
+/// Escapes a Prometheus label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serves the admin endpoint from the given listener, reporting on the controllers in the given
+/// registry as they are added and removed, until the returned task is aborted.
+pub(crate) fn serve(
+    listener: TcpListener,
+    health: HealthRegistry,
+    metrics: Option<Arc<PrometheusMetrics>>,
+) -> JoinHandle<()> {
+    tokio::task::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!(
+                        error = %e,
+                        error.source = %SourceChain(&e),
+                        "admin endpoint failed to accept a connection"
+                    );
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+            let health = health.clone();
+            let metrics = metrics.clone();
+            tokio::task::spawn(async move {
+                // Bounds the whole exchange, so that a client that stops reading the response can't
+                // hold on to the task either.
+                let result = match timeout(
+                    REQUEST_TIMEOUT,
+                    respond(stream, &health, metrics.as_deref()),
+                )
+                .await
+                {
+                    Ok(result) => result,
+                    Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "request timed out")),
+                };
+                if let Err(e) = result {
+                    debug!(
+                        error = %e,
+                        error.source = %SourceChain(&e),
+                        "admin endpoint failed to respond"
+                    );
+                }
+            });
+        }
+    })
+}
+
+/// Reads a single request from the stream, and writes the response to it before closing it.
+async fn respond(
+    mut stream: TcpStream,
+    health: &HealthRegistry,
+    metrics: Option<&PrometheusMetrics>,
+) -> io::Result<()> {
+    let request = read_request(&mut stream).await?;
+    let mut parts = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let (method, path) = (parts.next(), parts.next());
+    // Query strings aren't used, so they're ignored rather than failing the request.
+    let path = path.map(|path| path.split('?').next().unwrap_or_default());
+
+    let (status, content_type, body) = match (method, path) {
+        (Some("GET"), Some(path)) => route(path, health, metrics),
+        (Some(_), Some(_)) => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "only GET is supported\n".to_string(),
+        ),
+        _ => (
+            "400 Bad Request",
+            "text/plain",
+            "malformed request\n".to_string(),
+        ),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Reads from the stream until the end of the request's headers. Requests to the admin endpoint
+/// have no body.
+async fn read_request(stream: &mut TcpStream) -> io::Result<String> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request too large",
+            ));
+        }
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..read]);
+    }
+    Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+/// Returns the status, content type and body to respond to a GET of the given path with.
+fn route(
+    path: &str,
+    health: &HealthRegistry,
+    metrics: Option<&PrometheusMetrics>,
+) -> (&'static str, &'static str, String) {
+    let snapshots: Vec<ControllerHealth> = health
+        .read()
+        .unwrap()
+        .iter()
+        .map(Health::snapshot)
+        .collect();
+    match path {
+        "/healthz" => probe(ProbeReport::liveness(&snapshots)),
+        "/readyz" => probe(ProbeReport::readiness(&snapshots)),
+        "/status" => {
+            let status: Vec<_> = snapshots.iter().map(to_json).collect();
+            (
+                "200 OK",
+                "application/json",
+                serde_json::Value::from(status).to_string(),
+            )
+        }
+        "/metrics" => match metrics {
+            Some(metrics) => ("200 OK", "text/plain; version=0.0.4", metrics.render()),
+            None => (
+                "404 Not Found",
+                "text/plain",
+                "metrics are not configured\n".to_string(),
+            ),
+        },
+        _ => ("404 Not Found", "text/plain", "not found\n".to_string()),
+    }
+}
+
+/// Returns the response to a probe, listing why each failing controller failed it, if any did.
+fn probe(report: ProbeReport) -> (&'static str, &'static str, String) {
+    if report.passed() {
+        return ("200 OK", "text/plain", "ok\n".to_string());
+    }
+    let mut body = String::new();
+    for failure in &report.failing {
+        let _ = writeln!(body, "{}", failure);
+    }
+    ("503 Service Unavailable", "text/plain", body)
+}
+
+/// Converts a health snapshot to JSON, with the time of the last success in seconds since the
+/// Unix epoch.
+fn to_json(health: &ControllerHealth) -> serde_json::Value {
+    let last_success = health.last_success.map(|at| {
+        at.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64()
+    });
+    let last_error_at = health.last_error_at.map(|at| {
+        at.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64()
+    });
+    json!({
+        "name": health.name,
+        "running": health.running,
+        "exit_error": health.exit_error,
+        "state": health.state.as_str(),
+        "ready": health.ready,
+        "paused": health.paused,
+        "healthy": health.healthy,
+        "overloaded": health.overloaded,
+        "restarts": health.restarts,
+        "last_success": last_success,
+        "last_error": health.last_error,
+        "last_error_at": last_error_at,
+        "consecutive_failures": health.consecutive_failures,
+        "last_pass": health.last_pass.map(|stats| json!({
+            "processed": stats.processed,
+            "skipped": stats.skipped,
+        })),
+    })
+}
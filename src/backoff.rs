@@ -0,0 +1,87 @@
+//! Backoff configuration used to space out retries after consecutive failures.
+
+use std::time::Duration;
+
+use crate::jitter::{Jitter, JitterConfig};
+
+/// Describes an exponential backoff strategy. After the first failure the executor waits `base`,
+/// and each further consecutive failure multiplies the previous delay by `multiplier`, up to a
+/// ceiling of `max`. A success resets the delay back to `base`.
+///
+/// Many controllers failing on the same outage would otherwise all retry in lockstep, so each
+/// delay can be randomized with `with_jitter`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackoffConfig {
+    /// The delay to wait after the first failure.
+    pub base: Duration,
+    /// The largest delay that will ever be waited between attempts.
+    pub max: Duration,
+    /// The factor applied to the delay after each consecutive failure.
+    pub multiplier: f64,
+    /// When set, each delay is randomized by up to this much, without ever exceeding `max`.
+    pub jitter: Option<JitterConfig>,
+}
+
+impl BackoffConfig {
+    /// Create a new BackoffConfig.
+    pub fn new(base: Duration, max: Duration, multiplier: f64) -> BackoffConfig {
+        BackoffConfig {
+            base,
+            max,
+            multiplier,
+            jitter: None,
+        }
+    }
+
+    /// Returns this config, randomizing each delay with the given jitter.
+    pub fn with_jitter(self, jitter: JitterConfig) -> BackoffConfig {
+        BackoffConfig {
+            jitter: Some(jitter),
+            ..self
+        }
+    }
+
+    /// Computes the delay to wait after the given number of consecutive failures. Zero failures
+    /// means no delay at all.
+    pub fn delay(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::from_secs(0);
+        }
+
+        let exponent = (failures - 1).min(i32::MAX as u32) as i32;
+        let delay = self.base.as_secs_f64() * self.multiplier.max(1.0).powi(exponent);
+        if !delay.is_finite() || delay >= self.max.as_secs_f64() {
+            self.max
+        } else {
+            Duration::from_secs_f64(delay)
+        }
+    }
+
+    /// Computes the delay like `delay`, randomized by the configured jitter, if any, for the
+    /// named controller.
+    pub(crate) fn jittered_delay(&self, failures: u32, name: &str) -> Duration {
+        let delay = self.delay(failures);
+        let jitter = match self.jitter {
+            Some(jitter) => jitter,
+            None => return delay,
+        };
+        // A seeded jitter would randomize every delay by the same amount, so the seed is varied
+        // by the number of failures.
+        let jitter = match jitter.seed {
+            Some(seed) => jitter.with_seed(seed.wrapping_add(u64::from(failures))),
+            None => jitter,
+        };
+        Jitter::new(jitter, name).apply(delay).min(self.max)
+    }
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        BackoffConfig {
+            base: Duration::from_secs(1),
+            max: Duration::from_secs(5 * 60),
+            multiplier: 2.0,
+            jitter: None,
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Supports running controllers with different error types side by side in a single
+//! ControllerHost, by erasing their concrete error types.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::context::ReconcileContext;
+use crate::controller::{Controller, Draining, ReconcileOutcome};
+use crate::controller_host::ControllerHost;
+use crate::events::EventRecorder;
+use crate::trigger::Trigger;
+
+/// A ControllerHost that can run controllers with any error type. Add controllers to it with
+/// `add_boxed_controller`.
+pub type BoxedControllerHost = ControllerHost<BoxedError>;
+
+/// An error whose concrete type has been erased. It displays as, and has the same source as, the
+/// error it wraps. Use `get_ref` to downcast back to the original error.
+pub struct BoxedError(Box<dyn Error + Send + Sync>);
+
+impl BoxedError {
+    /// Returns a reference to the wrapped error.
+    pub fn get_ref(&self) -> &(dyn Error + Send + Sync + 'static) {
+        &*self.0
+    }
+
+    /// Returns the wrapped error.
+    pub fn into_inner(self) -> Box<dyn Error + Send + Sync> {
+        self.0
+    }
+}
+
+impl fmt::Debug for BoxedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for BoxedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl Error for BoxedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.0.source()
+    }
+}
+
+impl From<Box<dyn Error + Send + Sync>> for BoxedError {
+    fn from(error: Box<dyn Error + Send + Sync>) -> Self {
+        BoxedError(error)
+    }
+}
+
+/// Adapts a Controller with any error type into one whose error is a BoxedError.
+pub struct Boxed<C: Controller> {
+    controller: C,
+}
+
+impl<C: Controller> Boxed<C> {
+    /// Create a new Boxed controller wrapping the given controller.
+    pub fn new(controller: C) -> Boxed<C> {
+        Boxed { controller }
+    }
+}
+
+#[async_trait]
+impl<C: Controller> Controller for Boxed<C> {
+    type Error = BoxedError;
+
+    fn name(&self) -> &str {
+        self.controller.name()
+    }
+
+    fn phase(&self) -> u32 {
+        self.controller.phase()
+    }
+
+    async fn initialize(&mut self) -> Result<(), Self::Error> {
+        self.controller.initialize().await.map_err(boxed)
+    }
+
+    async fn reconcile(
+        &mut self,
+        ctx: &mut ReconcileContext,
+    ) -> Result<ReconcileOutcome, Self::Error> {
+        self.controller.reconcile(ctx).await.map_err(boxed)
+    }
+
+    async fn cleanup(&mut self, ctx: &mut ReconcileContext) -> Result<(), Self::Error> {
+        self.controller.cleanup(ctx).await.map_err(boxed)
+    }
+
+    fn resync_period(&self) -> Duration {
+        self.controller.resync_period()
+    }
+
+    fn cleanup_period(&self) -> Duration {
+        self.controller.cleanup_period()
+    }
+
+    fn validate(&self) -> Result<(), Self::Error> {
+        self.controller.validate().map_err(boxed)
+    }
+
+    fn has_cleanup(&self) -> bool {
+        self.controller.has_cleanup()
+    }
+
+    fn set_event_recorder(&mut self, events: Arc<dyn EventRecorder>) {
+        self.controller.set_event_recorder(events);
+    }
+
+    fn set_draining(&mut self, draining: Draining) {
+        self.controller.set_draining(draining);
+    }
+}
+
+fn boxed<E: Into<Box<dyn Error + Send + Sync>>>(error: E) -> BoxedError {
+    BoxedError(error.into())
+}
+
+impl BoxedControllerHost {
+    /// Adds a controller with any error type to the host, erasing its error type so that it can
+    /// run alongside controllers with other error types. See `add_controller`.
+    pub async fn add_boxed_controller<C>(&mut self, controller: C) -> Result<Trigger, BoxedError>
+    where
+        C: Controller + 'static,
+    {
+        self.add_controller(Box::new(Boxed::new(controller))).await
+    }
+}
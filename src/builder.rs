@@ -0,0 +1,477 @@
+//! Configures how a controller is run without the controller having to expose the knobs itself,
+//! and how a ControllerHost runs all of its controllers.
+
+use std::any::Any;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+
+use crate::backoff::BackoffConfig;
+use crate::context::{Extensions, ReconcileContext};
+use crate::controller::{Controller, ControllerError, Draining, ExecutorConfig, ReconcileOutcome};
+use crate::controller_host::ControllerHost;
+use crate::cron::CronSchedule;
+use crate::events::EventRecorder;
+use crate::jitter::JitterConfig;
+use crate::metrics::ControllerMetrics;
+use crate::supervision::SupervisionPolicy;
+
+/// Wraps any Controller to fluently configure how it is run, keeping configuration concerns out
+/// of the controller's business logic. Pass the result of `build` to
+/// `ControllerHost::add_controller`.
+///
+/// Anything left unset falls back to the controller's own periods, and the host's config.
+pub struct ControllerBuilder<C: Controller> {
+    controller: C,
+    phase: Option<u32>,
+    resync_period: Option<Duration>,
+    cleanup_period: Option<Duration>,
+    overrides: ConfigOverrides,
+}
+
+impl<C: Controller + 'static> ControllerBuilder<C> {
+    /// Create a new ControllerBuilder wrapping the given controller.
+    pub fn new(controller: C) -> ControllerBuilder<C> {
+        ControllerBuilder {
+            controller,
+            phase: None,
+            resync_period: None,
+            cleanup_period: None,
+            overrides: ConfigOverrides::default(),
+        }
+    }
+
+    /// Sets the startup phase of the controller, in place of its own `phase`.
+    pub fn phase(mut self, phase: u32) -> Self {
+        self.phase = Some(phase);
+        self
+    }
+
+    /// Sets how often the controller reconciles, in place of its own `resync_period`. Unless the
+    /// controller cleans up on a period of its own, which differs from its resync period, it
+    /// cleans up this often too.
+    pub fn resync_period(mut self, resync_period: Duration) -> Self {
+        self.resync_period = Some(resync_period);
+        self
+    }
+
+    /// Sets how often the controller cleans up, in place of its own `cleanup_period`.
+    pub fn cleanup_period(mut self, cleanup_period: Duration) -> Self {
+        self.cleanup_period = Some(cleanup_period);
+        self
+    }
+
+    /// Sets the timeout applied to each `reconcile` and `cleanup` call. See
+    /// `ExecutorConfig::reconcile_timeout`.
+    pub fn reconcile_timeout(mut self, reconcile_timeout: Duration) -> Self {
+        self.overrides.reconcile_timeout = Some(reconcile_timeout);
+        self
+    }
+
+    /// Sets the backoff applied after consecutive reconcile failures. See
+    /// `ExecutorConfig::backoff`.
+    pub fn backoff(mut self, backoff: BackoffConfig) -> Self {
+        self.overrides.backoff = Some(backoff);
+        self
+    }
+
+    /// Sets the backoff applied between failed attempts to initialize the controller. See
+    /// `ExecutorConfig::initialize_backoff`.
+    pub fn initialize_backoff(mut self, backoff: BackoffConfig) -> Self {
+        self.overrides.initialize_backoff = Some(backoff);
+        self
+    }
+
+    /// Sets how many times in a row the controller may fail to initialize before its control loop
+    /// gives up on it. See `ExecutorConfig::max_initialize_attempts`.
+    pub fn max_initialize_attempts(mut self, max_initialize_attempts: u32) -> Self {
+        self.overrides.max_initialize_attempts = Some(max_initialize_attempts);
+        self
+    }
+
+    /// Sets how many reconciles in a row the controller may fail before its control loop gives up
+    /// on it. See `ExecutorConfig::max_consecutive_failures`.
+    pub fn max_consecutive_failures(mut self, max_consecutive_failures: u32) -> Self {
+        self.overrides.max_consecutive_failures = Some(max_consecutive_failures);
+        self
+    }
+
+    /// Sets how many reconciles in a row may overrun the resync period before the controller is
+    /// reported as overloaded. See `ExecutorConfig::overloaded_after_overruns`.
+    pub fn overloaded_after_overruns(mut self, overloaded_after_overruns: u32) -> Self {
+        self.overrides.overloaded_after_overruns = Some(overloaded_after_overruns);
+        self
+    }
+
+    /// Sets how long the controller waits after a trigger before reconciling, coalescing the
+    /// triggers that arrive in the meantime. See `ExecutorConfig::trigger_debounce`.
+    pub fn trigger_debounce(mut self, trigger_debounce: Duration) -> Self {
+        self.overrides.trigger_debounce = Some(trigger_debounce);
+        self
+    }
+
+    /// Sets how resync and cleanup ticks missed while the control loop was busy are made up for.
+    /// See `ExecutorConfig::missed_tick_behavior`.
+    pub fn missed_tick_behavior(mut self, missed_tick_behavior: MissedTickBehavior) -> Self {
+        self.overrides.missed_tick_behavior = Some(missed_tick_behavior);
+        self
+    }
+
+    /// Sets whether the controller's control loop is restarted after it stops on its own. See
+    /// `ExecutorConfig::supervision`.
+    pub fn supervision(mut self, supervision: SupervisionPolicy) -> Self {
+        self.overrides.supervision = Some(supervision);
+        self
+    }
+
+    /// Sets whether a reconcile or cleanup still running when the controller is cancelled is left
+    /// to finish. See `ExecutorConfig::drain_on_shutdown`.
+    pub fn drain_on_shutdown(mut self, drain_on_shutdown: bool) -> Self {
+        self.overrides.drain_on_shutdown = Some(drain_on_shutdown);
+        self
+    }
+
+    /// Adds a value to every ReconcileContext the controller is called with, alongside any the
+    /// host's config provides, replacing one of the same type. See `ExecutorConfig::extensions`.
+    pub fn extension<T: Any + Send + Sync>(mut self, value: T) -> Self {
+        self.overrides.extensions.insert(value);
+        self
+    }
+
+    /// Sets the jitter applied to each resync period. See `ExecutorConfig::jitter`.
+    pub fn jitter(mut self, jitter: JitterConfig) -> Self {
+        self.overrides.jitter = Some(jitter);
+        self
+    }
+
+    /// Sets a cron schedule the controller resyncs on, in place of its resync period. See
+    /// `ExecutorConfig::resync_schedule`.
+    pub fn resync_schedule(mut self, schedule: CronSchedule) -> Self {
+        self.overrides.resync_schedule = Some(schedule);
+        self
+    }
+
+    /// Sets a cron schedule the controller cleans up on, in place of its cleanup period. See
+    /// `ExecutorConfig::cleanup_schedule`.
+    pub fn cleanup_schedule(mut self, schedule: CronSchedule) -> Self {
+        self.overrides.cleanup_schedule = Some(schedule);
+        self
+    }
+
+    /// Returns the controller being configured.
+    pub(crate) fn controller(&self) -> &C {
+        &self.controller
+    }
+
+    /// Finishes configuring the controller, ready to be added to a ControllerHost.
+    pub fn build(self) -> ControllerRegistration<C::Error> {
+        ControllerRegistration {
+            controller: Box::new(Configured {
+                controller: self.controller,
+                phase: self.phase,
+                resync_period: self.resync_period,
+                cleanup_period: self.cleanup_period,
+            }),
+            overrides: self.overrides,
+        }
+    }
+}
+
+impl<C: Controller + 'static> From<C> for ControllerBuilder<C> {
+    fn from(controller: C) -> Self {
+        ControllerBuilder::new(controller)
+    }
+}
+
+/// Fluently configures a ControllerHost, as a single entry point to everything a host can be
+/// configured with, and the controllers it runs. Create one with `ControllerHost::builder`, and
+/// finish it with `build`.
+///
+/// The config set here is the default for every controller added, including those added to the
+/// host later, and any ControllerBuilder passed to `add_controller` can override it for its own
+/// controller.
+pub struct ControllerHostBuilder<E: Error + Send + Sync + 'static> {
+    config: ExecutorConfig,
+    errors: Option<mpsc::Sender<ControllerError<E>>>,
+    max_concurrent_reconciles: Option<usize>,
+    shutdown_timeout: Option<Duration>,
+    controllers: Vec<ControllerRegistration<E>>,
+}
+
+impl<E> ControllerHostBuilder<E>
+where
+    E: Error + Send + Sync + 'static,
+{
+    /// Create a new ControllerHostBuilder, with the default config and no controllers.
+    pub fn new() -> ControllerHostBuilder<E> {
+        ControllerHostBuilder {
+            config: ExecutorConfig::default(),
+            errors: None,
+            max_concurrent_reconciles: None,
+            shutdown_timeout: None,
+            controllers: vec![],
+        }
+    }
+
+    /// Sets the config controllers are run with, replacing any set on this builder before. See
+    /// `ControllerHost::with_config`.
+    pub fn config(mut self, config: ExecutorConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Sets the backoff applied after consecutive reconcile failures. See
+    /// `ExecutorConfig::backoff`.
+    pub fn backoff(mut self, backoff: BackoffConfig) -> Self {
+        self.config.backoff = Some(backoff);
+        self
+    }
+
+    /// Sets the timeout applied to each `reconcile` and `cleanup` call. See
+    /// `ExecutorConfig::reconcile_timeout`.
+    pub fn reconcile_timeout(mut self, reconcile_timeout: Duration) -> Self {
+        self.config.reconcile_timeout = Some(reconcile_timeout);
+        self
+    }
+
+    /// Sets where every controller's measurements are recorded. See `ExecutorConfig::metrics`.
+    pub fn metrics(mut self, metrics: Arc<dyn ControllerMetrics>) -> Self {
+        self.config.metrics = metrics;
+        self
+    }
+
+    /// Sets a channel that is sent every error any of the controllers run into. See
+    /// `ControllerHost::set_errors`.
+    pub fn errors(mut self, errors: mpsc::Sender<ControllerError<E>>) -> Self {
+        self.errors = Some(errors);
+        self
+    }
+
+    /// Limits how many controllers are inside `reconcile` or `cleanup` at once. See
+    /// `ControllerHost::set_max_concurrent_reconciles`.
+    pub fn max_concurrent_reconciles(mut self, max_concurrent_reconciles: usize) -> Self {
+        self.max_concurrent_reconciles = Some(max_concurrent_reconciles);
+        self
+    }
+
+    /// Bounds how long controllers are given to stop once the process is asked to shut down. See
+    /// `ControllerHost::set_shutdown_timeout`.
+    pub fn shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.shutdown_timeout = Some(shutdown_timeout);
+        self
+    }
+
+    /// Adds a controller for the host to run, which is validated and registered, in the order
+    /// added, by `build`. Use `ControllerHost::add_controller` on the built host instead for a
+    /// controller whose Trigger is needed.
+    pub fn add_controller(mut self, controller: impl Into<ControllerRegistration<E>>) -> Self {
+        self.controllers.push(controller.into());
+        self
+    }
+
+    /// Finishes configuring the host, registering every controller added. Returns the error the
+    /// first controller `Controller::validate` rejected failed with, if any.
+    pub async fn build(self) -> Result<ControllerHost<E>, E> {
+        let mut host = ControllerHost::with_config(self.config);
+        host.set_errors(self.errors);
+        host.set_max_concurrent_reconciles(self.max_concurrent_reconciles);
+        host.set_shutdown_timeout(self.shutdown_timeout);
+        for controller in self.controllers {
+            host.add_controller(controller).await?;
+        }
+        Ok(host)
+    }
+}
+
+impl<E> Default for ControllerHostBuilder<E>
+where
+    E: Error + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A controller ready to be added to a ControllerHost, along with any config it should be run
+/// with in place of the host's. Boxed controllers convert into one as is.
+pub struct ControllerRegistration<E: Error + Send + Sync + 'static> {
+    pub(crate) controller: Box<dyn Controller<Error = E>>,
+    pub(crate) overrides: ConfigOverrides,
+}
+
+impl<E> From<Box<dyn Controller<Error = E>>> for ControllerRegistration<E>
+where
+    E: Error + Send + Sync + 'static,
+{
+    fn from(controller: Box<dyn Controller<Error = E>>) -> Self {
+        ControllerRegistration {
+            controller,
+            overrides: ConfigOverrides::default(),
+        }
+    }
+}
+
+impl<C, E> From<Box<C>> for ControllerRegistration<E>
+where
+    C: Controller<Error = E> + 'static,
+    E: Error + Send + Sync + 'static,
+{
+    fn from(controller: Box<C>) -> Self {
+        ControllerRegistration {
+            controller,
+            overrides: ConfigOverrides::default(),
+        }
+    }
+}
+
+impl<C: Controller + 'static> From<ControllerBuilder<C>> for ControllerRegistration<C::Error> {
+    fn from(builder: ControllerBuilder<C>) -> Self {
+        builder.build()
+    }
+}
+
+/// The parts of an ExecutorConfig set on a ControllerBuilder.
+#[derive(Default)]
+pub(crate) struct ConfigOverrides {
+    backoff: Option<BackoffConfig>,
+    initialize_backoff: Option<BackoffConfig>,
+    max_initialize_attempts: Option<u32>,
+    reconcile_timeout: Option<Duration>,
+    max_consecutive_failures: Option<u32>,
+    overloaded_after_overruns: Option<u32>,
+    trigger_debounce: Option<Duration>,
+    missed_tick_behavior: Option<MissedTickBehavior>,
+    supervision: Option<SupervisionPolicy>,
+    drain_on_shutdown: Option<bool>,
+    extensions: Extensions,
+    jitter: Option<JitterConfig>,
+    resync_schedule: Option<CronSchedule>,
+    cleanup_schedule: Option<CronSchedule>,
+}
+
+impl ConfigOverrides {
+    /// Returns the given config with every override that was set applied to it.
+    pub(crate) fn apply(&self, mut config: ExecutorConfig) -> ExecutorConfig {
+        if let Some(backoff) = self.backoff {
+            config.backoff = Some(backoff);
+        }
+        if let Some(initialize_backoff) = self.initialize_backoff {
+            config.initialize_backoff = initialize_backoff;
+        }
+        if let Some(max_initialize_attempts) = self.max_initialize_attempts {
+            config.max_initialize_attempts = Some(max_initialize_attempts);
+        }
+        if let Some(reconcile_timeout) = self.reconcile_timeout {
+            config.reconcile_timeout = Some(reconcile_timeout);
+        }
+        if let Some(max_consecutive_failures) = self.max_consecutive_failures {
+            config.max_consecutive_failures = Some(max_consecutive_failures);
+        }
+        if let Some(overloaded_after_overruns) = self.overloaded_after_overruns {
+            config.overloaded_after_overruns = overloaded_after_overruns;
+        }
+        if let Some(trigger_debounce) = self.trigger_debounce {
+            config.trigger_debounce = trigger_debounce;
+        }
+        if let Some(missed_tick_behavior) = self.missed_tick_behavior {
+            config.missed_tick_behavior = missed_tick_behavior;
+        }
+        if let Some(supervision) = self.supervision {
+            config.supervision = supervision;
+        }
+        if let Some(drain_on_shutdown) = self.drain_on_shutdown {
+            config.drain_on_shutdown = drain_on_shutdown;
+        }
+        config.extensions.extend(&self.extensions);
+        if let Some(jitter) = self.jitter {
+            config.jitter = Some(jitter);
+        }
+        if let Some(resync_schedule) = &self.resync_schedule {
+            config.resync_schedule = Some(resync_schedule.clone());
+        }
+        if let Some(cleanup_schedule) = &self.cleanup_schedule {
+            config.cleanup_schedule = Some(cleanup_schedule.clone());
+        }
+        config
+    }
+}
+
+/// A controller whose phase or periods have been overridden by a ControllerBuilder.
+struct Configured<C: Controller> {
+    controller: C,
+    phase: Option<u32>,
+    resync_period: Option<Duration>,
+    cleanup_period: Option<Duration>,
+}
+
+#[async_trait]
+impl<C: Controller> Controller for Configured<C> {
+    type Error = C::Error;
+
+    fn name(&self) -> &str {
+        self.controller.name()
+    }
+
+    fn phase(&self) -> u32 {
+        self.phase.unwrap_or_else(|| self.controller.phase())
+    }
+
+    async fn initialize(&mut self) -> Result<(), Self::Error> {
+        self.controller.initialize().await
+    }
+
+    async fn reconcile(
+        &mut self,
+        ctx: &mut ReconcileContext,
+    ) -> Result<ReconcileOutcome, Self::Error> {
+        self.controller.reconcile(ctx).await
+    }
+
+    async fn cleanup(&mut self, ctx: &mut ReconcileContext) -> Result<(), Self::Error> {
+        self.controller.cleanup(ctx).await
+    }
+
+    fn resync_period(&self) -> Duration {
+        match self.resync_period {
+            Some(resync_period) => resync_period,
+            None => self.controller.resync_period(),
+        }
+    }
+
+    fn cleanup_period(&self) -> Duration {
+        match self.cleanup_period {
+            Some(cleanup_period) => cleanup_period,
+            None => {
+                // A controller cleaning up as often as it reconciles is taken to be relying on the
+                // default, so that its cleanups follow an overridden resync period too.
+                let cleanup_period = self.controller.cleanup_period();
+                if self.resync_period.is_some() && cleanup_period == self.controller.resync_period()
+                {
+                    self.resync_period()
+                } else {
+                    cleanup_period
+                }
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), Self::Error> {
+        self.controller.validate()
+    }
+
+    fn has_cleanup(&self) -> bool {
+        self.controller.has_cleanup()
+    }
+
+    fn set_event_recorder(&mut self, events: Arc<dyn EventRecorder>) {
+        self.controller.set_event_recorder(events);
+    }
+
+    fn set_draining(&mut self, draining: Draining) {
+        self.controller.set_draining(draining);
+    }
+}
@@ -0,0 +1,104 @@
+//! Abstracts the time source control loops run on, so that their timing can be tested without
+//! real sleeps.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// A source of time. Control loops use the clock in their ExecutorConfig for every resync,
+/// backoff, requeue and timeout, so swapping in a ManualClock makes their timing deterministic.
+/// WorkQueues and BucketRateLimiters are given a clock of their own, with `with_clock`.
+pub trait Clock: Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+
+    /// Returns a future that completes once the given time has been reached.
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()>;
+
+    /// Returns a future that completes once the given duration has passed. One too long to reach
+    /// on the clock never completes.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        match self.now().checked_add(duration) {
+            Some(deadline) => self.sleep_until(deadline),
+            None => Box::pin(futures::future::pending()),
+        }
+    }
+}
+
+/// The Clock used by default, which is tokio's clock. Under tokio's test utilities it can be
+/// paused and advanced too.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep_until(deadline))
+    }
+}
+
+/// A Clock that only moves when it is told to with `advance`, for testing. Clones share the same
+/// time.
+///
+/// Advancing the clock wakes the sleepers that are due, but they only run once the test yields
+/// to the runtime, for example with `tokio::task::yield_now`.
+#[derive(Clone)]
+pub struct ManualClock {
+    inner: Arc<ManualInner>,
+}
+
+struct ManualInner {
+    now: Mutex<Instant>,
+    /// Notified whenever the time moves.
+    advanced: Notify,
+}
+
+impl ManualClock {
+    /// Create a new ManualClock, stopped at the current time.
+    pub fn new() -> ManualClock {
+        ManualClock {
+            inner: Arc::new(ManualInner {
+                now: Mutex::new(Instant::now()),
+                advanced: Notify::new(),
+            }),
+        }
+    }
+
+    /// Moves the clock forward by the given duration, waking every sleeper that is now due.
+    pub fn advance(&self, duration: Duration) {
+        *self.inner.now.lock().unwrap() += duration;
+        self.inner.advanced.notify_waiters();
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.inner.now.lock().unwrap()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            loop {
+                // Created before checking the time so that an advance in between isn't missed.
+                let advanced = inner.advanced.notified();
+                if *inner.now.lock().unwrap() >= deadline {
+                    return;
+                }
+                advanced.await;
+            }
+        })
+    }
+}
@@ -0,0 +1,198 @@
+//! Carries request scoped values, such as a trace or tenant id, into each call to a controller's
+//! `reconcile` and `cleanup`, alongside a cancellation Context.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use futures::FutureExt;
+use tokio::time::Instant;
+use tokio_context::context::Context;
+
+/// A map of values keyed by their type, like `http::Extensions`, so that unrelated code can each
+/// store a value for controllers to read without agreeing on a shared struct. Wrap plain values
+/// like ids in a newtype of their own, so that they can't collide.
+///
+/// Values are kept behind an `Arc`, so cloning the map, as the executor does for every call, is
+/// cheap.
+#[derive(Clone, Default)]
+pub struct Extensions(HashMap<TypeId, Arc<dyn Any + Send + Sync>>);
+
+impl Extensions {
+    /// Create an empty Extensions map.
+    pub fn new() -> Extensions {
+        Extensions::default()
+    }
+
+    /// Stores the given value, replacing any value of the same type.
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) {
+        self.0.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// Returns the value of the given type, if one was stored.
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.0
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Removes the value of the given type, returning whether there was one.
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> bool {
+        self.0.remove(&TypeId::of::<T>()).is_some()
+    }
+
+    /// Stores every value from the given map, replacing any of the same type.
+    pub fn extend(&mut self, other: &Extensions) {
+        self.0
+            .extend(other.0.iter().map(|(id, value)| (*id, value.clone())));
+    }
+
+    /// Returns how many values are stored.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether no values are stored.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.0.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The number of the reconcile pass a call belongs to, counted from 1 each time the control loop
+/// starts. Stored in every ReconcileContext by the executor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Iteration(pub u64);
+
+/// When the call will be abandoned by `ExecutorConfig::reconcile_timeout`. Stored in a
+/// ReconcileContext by the executor only when a timeout is configured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deadline(pub Instant);
+
+/// How much work a reconcile pass did, as reported to its ReconcileContext, so that a controller
+/// doing useful work can be told apart from one spinning over items that are already up to date.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ReconcileStats {
+    /// The number of items the pass reconciled.
+    pub processed: u64,
+    /// The number of items the pass found already up to date, or otherwise passed over.
+    pub skipped: u64,
+}
+
+/// Passed to each call to `Controller::reconcile` and `Controller::cleanup`. Wraps a cancellation
+/// Context, which is done once the control loop is cancelled, along with the Extensions the call
+/// was made with.
+///
+/// The executor fills in the extensions from `ExecutorConfig::extensions`, which is where a host
+/// provides values shared by its controllers, and adds an `Iteration` and, with a timeout, a
+/// `Deadline` for each call.
+///
+/// Controllers report how many items a pass processed and skipped with `record_processed` and
+/// `record_skipped`, which the executor passes on to its metrics and health. These take `&self`,
+/// so that items reconciled concurrently can each report.
+pub struct ReconcileContext {
+    ctx: Context,
+    extensions: Extensions,
+    processed: AtomicU64,
+    skipped: AtomicU64,
+}
+
+impl ReconcileContext {
+    /// Create a new ReconcileContext, cancelled along with the given Context, and without any
+    /// extensions. Useful for calling a controller directly, such as from a test.
+    pub fn new(ctx: Context) -> ReconcileContext {
+        Self::with_extensions(ctx, Extensions::new())
+    }
+
+    /// Create a new ReconcileContext, cancelled along with the given Context, that carries the
+    /// given extensions.
+    pub fn with_extensions(ctx: Context, extensions: Extensions) -> ReconcileContext {
+        ReconcileContext {
+            ctx,
+            extensions,
+            processed: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+        }
+    }
+
+    /// Waits until the control loop is cancelled. A call only sees this when it is left to finish
+    /// by `ExecutorConfig::drain_on_shutdown`, as it is otherwise abandoned.
+    pub async fn done(&mut self) {
+        self.ctx.done().await
+    }
+
+    /// Returns whether the control loop has been cancelled, without waiting. A long pass left to
+    /// finish by `ExecutorConfig::drain_on_shutdown` can check this between items of a batch, to
+    /// bail out cooperatively rather than working through all of them.
+    pub fn is_done(&mut self) -> bool {
+        self.ctx.done().now_or_never().is_some()
+    }
+
+    /// Returns the cancellation Context, to hand on to code that takes one.
+    pub fn context(&mut self) -> &mut Context {
+        &mut self.ctx
+    }
+
+    /// Returns the value of the given type, if the call was made with one. Shorthand for
+    /// `extensions().get()`.
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.extensions.get()
+    }
+
+    /// Returns the extensions the call was made with.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Returns the extensions the call was made with, to store values in for the rest of the call.
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// Returns the number of the reconcile pass the call belongs to, or 0 outside of one. See
+    /// `Iteration`.
+    pub fn iteration(&self) -> u64 {
+        self.get::<Iteration>().map_or(0, |iteration| iteration.0)
+    }
+
+    /// Returns when the call will be abandoned, if it has a timeout. See `Deadline`.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.get::<Deadline>().map(|deadline| deadline.0)
+    }
+
+    /// Records that the call processed the given number of items.
+    pub fn record_processed(&self, items: u64) {
+        self.processed.fetch_add(items, Ordering::Relaxed);
+    }
+
+    /// Records that the call skipped the given number of items, such as ones already up to date.
+    pub fn record_skipped(&self, items: u64) {
+        self.skipped.fetch_add(items, Ordering::Relaxed);
+    }
+
+    /// Returns how many items the call has recorded processing and skipping so far.
+    pub fn stats(&self) -> ReconcileStats {
+        ReconcileStats {
+            processed: self.processed.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl fmt::Debug for ReconcileContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconcileContext")
+            .field("extensions", &self.extensions)
+            .field("stats", &self.stats())
+            .finish_non_exhaustive()
+    }
+}
@@ -1,42 +1,174 @@
 //! Creates control loops around the provided business logic.
 
+use std::any::Any;
 use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tracing::{error, info};
+use std::time::SystemTime;
+use tracing::{debug, error, field, info, info_span, warn, Instrument};
 
 use async_trait::async_trait;
-use tokio::{sync::Mutex, task::JoinHandle, time::interval};
-use tokio_context::context::Context;
+use futures::future::{pending, BoxFuture, FutureExt};
+use tokio::{
+    sync::{mpsc, watch, Mutex, Notify, OwnedSemaphorePermit, Semaphore},
+    task::JoinHandle,
+    time::{Instant, MissedTickBehavior},
+};
+use tokio_context::context::{Context, Handle};
+
+use crate::backoff::BackoffConfig;
+use crate::clock::{Clock, TokioClock};
+use crate::context::{Deadline, Extensions, Iteration, ReconcileContext};
+use crate::cron::CronSchedule;
+use crate::events::{EventRecorder, EventType, LoggingEventRecorder};
+use crate::health::{ControllerHealth, Health};
+use crate::jitter::{Jitter, JitterConfig};
+use crate::metrics::{ControllerMetrics, NoopMetrics};
+use crate::supervision::SupervisionPolicy;
+use crate::trigger::Trigger;
+
+/// Derives `Controller` for simple controllers, generating every method but `reconcile` from a
+/// `#[controller(...)]` attribute. Enabled by the `derive` feature.
+#[cfg(feature = "derive")]
+pub use reconciliation_derive::Controller;
+
+/// The outcome of a successful call to `Controller::reconcile`, telling the executor when the
+/// controller would like to be reconciled next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReconcileOutcome {
+    /// Reconciliation is complete. The controller will be reconciled again on the next resync
+    /// tick.
+    #[default]
+    Done,
+    /// Reconciliation is incomplete, and the controller should be reconciled again immediately.
+    Requeue,
+    /// Reconciliation is incomplete, and the controller should be reconciled again after the
+    /// given duration. If the next resync tick comes sooner, the controller is reconciled then.
+    RequeueAfter(std::time::Duration),
+}
+
+/// Lets a reconcile that has nothing to say about when it runs next, such as one written before
+/// outcomes existed, finish with `Ok(().into())`.
+impl From<()> for ReconcileOutcome {
+    fn from(_: ()) -> Self {
+        ReconcileOutcome::Done
+    }
+}
 
 /// Defines the required methods that must be implemented to specify the behavior of a given
 /// Controller instance.
 #[async_trait]
-pub trait Controller: Send + Sync {
+pub trait Controller: Send {
     /// Provide an error type that this controller should return. If you would like to run it
     /// alongside other controllers within a single ControllerHost, then the Error type for each
-    /// Controller must be the same.
+    /// Controller must be the same, unless the host is a `BoxedControllerHost`, which erases each
+    /// controller's error type.
     type Error: Error + 'static + Sync + Send;
 
-    /// Provide initial setup for the given Controller if necessary, otherwise simply return `Ok`.
-    async fn initialize(&mut self) -> Result<(), Self::Error>;
+    /// Provide a human readable name for this controller, used to tell controllers apart in logs
+    /// when several run within a single ControllerHost. Defaults to the controller's type name.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Provide the startup phase this controller belongs to. Within a ControllerHost, a
+    /// controller only initializes once every controller in an earlier phase has initialized,
+    /// for example so that one controller can seed reference data the others read. Defaults to
+    /// 0.
+    fn phase(&self) -> u32 {
+        0
+    }
+
+    /// Provide initial setup for the given Controller if necessary. Defaults to doing nothing.
+    async fn initialize(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
 
     /// Provide the necessary reconciliation logic for this controller. This generally requires
     /// fetching all of the specs that the controller is responsible for reconciling, doing some
     /// necessary work, and then updating the relevant status for the spec the controller is in
     /// charge of.
-    async fn reconcile(&mut self) -> Result<(), Self::Error>;
+    ///
+    /// Return `ReconcileOutcome::Done` to wait for the next resync tick, or one of the requeue
+    /// variants to ask to be reconciled again sooner.
+    ///
+    /// The given ReconcileContext carries the values the call was made with, such as its
+    /// `Iteration`.
+    async fn reconcile(
+        &mut self,
+        ctx: &mut ReconcileContext,
+    ) -> Result<ReconcileOutcome, Self::Error>;
 
     /// Provide the necessary logic to handle cleaning up soft deleted specs that have stayed
-    /// around passed an acceptable retention period, as defined by the controller.
-    async fn cleanup(&mut self) -> Result<(), Self::Error>;
+    /// around passed an acceptable retention period, as defined by the controller. Defaults to
+    /// doing nothing, in which case `has_cleanup` should return `false` too, so that the executor
+    /// doesn't wake up to call it.
+    async fn cleanup(&mut self, _ctx: &mut ReconcileContext) -> Result<(), Self::Error> {
+        Ok(())
+    }
 
     /// Retrieve the resync period for this controller. The resync period is how often this
-    /// controller will engage its control loop (reconciliation and deletion) even if it has
-    /// received no triggering events.
-    async fn resync_period(&self) -> std::time::Duration;
+    /// controller will reconcile even if it has received no triggering events. Periods shorter
+    /// than `MIN_PERIOD`, including zero, are raised to it by the ControllerExecutor, rather than
+    /// reconciling in a tight loop. Reject them with `validate` to fail fast instead.
+    ///
+    /// This is read when the controller is added to a ControllerExecutor, and is synchronous, as
+    /// it is almost always a field or a constant. It used to be async, and controllers migrating
+    /// from that only need to drop the `async` and any `.await`. Periods that are only known once
+    /// something has been awaited can be applied with `ControllerExecutor::set_resync_period`.
+    fn resync_period(&self) -> std::time::Duration;
+
+    /// Retrieve the cleanup period for this controller, which is how often `cleanup` is run. It is
+    /// scheduled independently of reconciliation, as sweeping soft deleted specs is usually needed
+    /// far less often. Raised to `MIN_PERIOD` like the resync period. Defaults to the resync
+    /// period.
+    fn cleanup_period(&self) -> std::time::Duration {
+        self.resync_period()
+    }
+
+    /// Check that this controller is configured sensibly, such as with a non-zero retention
+    /// period, so that a misconfigured controller is rejected with a clear error when it is added
+    /// to a ControllerHost, rather than misbehaving once it runs. Called by
+    /// `ControllerHost::add_controller`, before the controller is ever initialized. Defaults to
+    /// `Ok`.
+    fn validate(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Return whether this controller has anything to clean up. Controllers that don't own any
+    /// deletable data can return `false`, so that the executor never calls `cleanup` nor wakes up
+    /// for its schedule. Called once by the ControllerExecutor. Defaults to `true`.
+    fn has_cleanup(&self) -> bool {
+        true
+    }
+
+    /// Receive the EventRecorder this controller should record events about the items it
+    /// reconciles to. Called once by the ControllerExecutor, before the controller is
+    /// initialized. Controllers that record events should hold on to it; by default it is
+    /// dropped.
+    fn set_event_recorder(&mut self, _events: Arc<dyn EventRecorder>) {}
+
+    /// Receive the signal that tells this controller the control loop is draining, with
+    /// `ExecutorConfig::drain_on_shutdown`. Called once by the ControllerExecutor, before the
+    /// controller is initialized. Controllers that work through many items in a single reconcile,
+    /// like `Keyed`, should stop taking on new items once it is set, and finish the ones in hand;
+    /// by default it is dropped.
+    fn set_draining(&mut self, _draining: Draining) {}
 }
 
-/// A wrapper type that ensures we can send a given controller between tasks safely.
+/// The shortest resync or cleanup period a ControllerExecutor runs a controller with. Shorter
+/// periods, which would otherwise have the control loop tick back to back, are raised to this,
+/// with a warning.
+pub const MIN_PERIOD: std::time::Duration = std::time::Duration::from_millis(1);
+
+/// Shares a controller between its ControllerExecutor and the task running its control loop. The
+/// control loop is the only one calling into the controller while it runs, so the lock is
+/// uncontended, but sharing it rather than moving it into the task means the controller outlives
+/// the task: it can be started again after being cancelled or aborted, run with `run_once`
+/// between runs, and swapped out by `replace`, which waits for the call in progress to finish.
 struct AsyncSafeController<E: Error + Sync + Send + 'static>(
     Arc<Mutex<Box<dyn Controller<Error = E>>>>,
 );
@@ -56,41 +188,207 @@ where
     }
 }
 
-#[async_trait]
-impl<E> Controller for AsyncSafeController<E>
+// Only the async calls the control loop makes are forwarded, as the inner controller can't be
+// locked from a synchronous method. ControllerExecutor calls the rest before wrapping the
+// controller.
+impl<E> AsyncSafeController<E>
 where
     E: Error + Sync + Send + 'static,
 {
-    type Error = E;
-
-    async fn initialize(&mut self) -> Result<(), Self::Error> {
+    async fn initialize(&self) -> Result<(), E> {
         self.0.lock().await.initialize().await
     }
 
-    async fn reconcile(&mut self) -> Result<(), Self::Error> {
-        self.0.lock().await.reconcile().await
+    async fn reconcile(&self, ctx: &mut ReconcileContext) -> Result<ReconcileOutcome, E> {
+        self.0.lock().await.reconcile(ctx).await
     }
 
-    async fn cleanup(&mut self) -> Result<(), Self::Error> {
-        self.0.lock().await.cleanup().await
+    async fn cleanup(&self, ctx: &mut ReconcileContext) -> Result<(), E> {
+        self.0.lock().await.cleanup(ctx).await
+    }
+}
+
+/// Holds the knobs that tune how a ControllerExecutor drives its controller. The defaults
+/// preserve the plain resync-period behavior.
+#[derive(Clone)]
+pub struct ExecutorConfig {
+    /// When set, consecutive reconcile failures delay the next reconcile according to this
+    /// backoff instead of waiting for the next resync tick.
+    pub backoff: Option<BackoffConfig>,
+    /// Spaces out retries of a failing `initialize`, independently of the resync period, so that
+    /// a controller waiting on a slow dependency neither starts slowly nor hammers it.
+    pub initialize_backoff: BackoffConfig,
+    /// When set, the control loop gives up on a controller that fails to initialize this many
+    /// times in a row, such as one that is misconfigured, and exits with `ControllerExit::Failed`,
+    /// rather than retrying it forever. The controller then never becomes ready.
+    pub max_initialize_attempts: Option<u32>,
+    /// When set, each call to `reconcile` and `cleanup` is abandoned if it has not completed
+    /// within this duration. A timed out reconcile counts as a failure.
+    pub reconcile_timeout: Option<std::time::Duration>,
+    /// The number of resync periods a controller may go without a successful reconcile before it
+    /// reports itself as unhealthy.
+    pub unhealthy_after_periods: u32,
+    /// The number of reconciles in a row that may each take longer than the resync period before
+    /// the controller is reported as overloaded, with a warning and `ControllerHealth::overloaded`,
+    /// as it is then always behind, and would be better served by a longer period or by
+    /// reconciling concurrently. The flag clears on the next reconcile that finishes within the
+    /// period. 0 turns the check off.
+    pub overloaded_after_overruns: u32,
+    /// When set, the control loop gives up on a controller that fails this many reconciles in a
+    /// row, and exits with `ControllerExit::Failed`, rather than retrying it forever.
+    pub max_consecutive_failures: Option<u32>,
+    /// Whether the control loop is restarted after it stops on its own, by giving up after
+    /// `max_consecutive_failures` or `max_initialize_attempts`, or panicking. By default it is
+    /// left stopped.
+    pub supervision: SupervisionPolicy,
+    /// Receives the duration and result of every reconcile and cleanup call.
+    pub metrics: Arc<dyn ControllerMetrics>,
+    /// When set, each resync period is randomized so that controllers with the same period don't
+    /// all reconcile at once.
+    pub jitter: Option<JitterConfig>,
+    /// When set, the controller resyncs at the times this cron schedule matches, rather than
+    /// every resync period. Jitter doesn't apply. The resync period is still what the health
+    /// check's staleness threshold is measured in, so set it to about the gap between scheduled
+    /// times.
+    pub resync_schedule: Option<CronSchedule>,
+    /// When set, the controller cleans up at the times this cron schedule matches, rather than
+    /// every cleanup period. A daily `0 2 * * *` often suits cleanup better than a short interval.
+    ///
+    /// Both schedules read the wall clock once, when the control loop starts, and follow `clock`
+    /// from there, so that they can be tested with a ManualClock. A wall clock that is later set
+    /// to a new time is picked up the next time the control loop is started.
+    pub cleanup_schedule: Option<CronSchedule>,
+    /// The source of time for every resync, backoff, requeue and timeout. Swap in a ManualClock
+    /// to test timing deterministically. A Keyed controller's WorkQueue waits out the retries of
+    /// its keys on a clock of its own, given with `WorkQueue::with_clock`.
+    pub clock: Arc<dyn Clock>,
+    /// Handed to the controller with `Controller::set_event_recorder`, and records a `Warning`
+    /// event whenever a call into the controller fails. Defaults to logging each event.
+    pub events: Arc<dyn EventRecorder>,
+    /// What happens to a reconcile or cleanup that is still running when the control loop is
+    /// cancelled. By default it is abandoned right away. When set, the controller is told it is
+    /// draining through `Controller::set_draining`, and the call is left to finish before the
+    /// control loop exits, so that `Keyed` workers finish the keys they hold without taking new
+    /// ones. A drain is still bounded by `ControllerHost::cancel_all_timeout`.
+    pub drain_on_shutdown: bool,
+    /// Handed to every call to `reconcile` and `cleanup` through its ReconcileContext, for values
+    /// the controller should be able to read, such as a tenant id. Empty by default.
+    pub extensions: Extensions,
+    /// When set, a permit is held from this semaphore for the length of every call to `reconcile`
+    /// and `cleanup`, so that controllers sharing it are only ever inside that many calls at once,
+    /// however their resyncs line up. Size it to the connection pool they share. Clones of the
+    /// config share the semaphore. See `ControllerHost::set_max_concurrent_reconciles`.
+    ///
+    /// Time spent waiting for a permit doesn't count towards `reconcile_timeout`.
+    pub concurrency_limit: Option<Arc<Semaphore>>,
+    /// How long the control loop waits after a trigger before reconciling, so that a burst of
+    /// triggers, such as one per write, is coalesced into a single pass rather than a pass each.
+    /// A resync tick or requeue that comes due within the window still reconciles right away.
+    /// Zero, the default, reconciles as soon as a trigger arrives.
+    pub trigger_debounce: std::time::Duration,
+    /// How resync and cleanup ticks that came due while the control loop was busy, such as with a
+    /// reconcile that took longer than the resync period, are made up for. `Skip`, the default,
+    /// fires one tick right away and then carries on with the ticks still to come, so that a slow
+    /// controller isn't reconciled back to back. `Burst` fires every missed tick right away, one
+    /// after another, and `Delay` restarts the schedule from the late tick.
+    pub missed_tick_behavior: MissedTickBehavior,
+}
+
+impl Default for ExecutorConfig {
+    fn default() -> Self {
+        ExecutorConfig {
+            backoff: None,
+            initialize_backoff: BackoffConfig::default(),
+            max_initialize_attempts: None,
+            reconcile_timeout: None,
+            unhealthy_after_periods: 3,
+            overloaded_after_overruns: 3,
+            max_consecutive_failures: None,
+            supervision: SupervisionPolicy::default(),
+            metrics: Arc::new(NoopMetrics),
+            jitter: None,
+            resync_schedule: None,
+            cleanup_schedule: None,
+            clock: Arc::new(TokioClock),
+            events: Arc::new(LoggingEventRecorder),
+            drain_on_shutdown: false,
+            extensions: Extensions::new(),
+            concurrency_limit: None,
+            trigger_debounce: std::time::Duration::ZERO,
+            missed_tick_behavior: MissedTickBehavior::Skip,
+        }
     }
+}
 
-    // TODO: Had to add Sync to the Controller constraints specifically so this layer could be
-    // verified that sending Duration was safe. Try to think of a better solution. Seems silly to
-    // add Sync just for this.
-    async fn resync_period(&self) -> std::time::Duration {
-        self.0.lock().await.resync_period().await
+impl fmt::Debug for ExecutorConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExecutorConfig")
+            .field("backoff", &self.backoff)
+            .field("initialize_backoff", &self.initialize_backoff)
+            .field("max_initialize_attempts", &self.max_initialize_attempts)
+            .field("reconcile_timeout", &self.reconcile_timeout)
+            .field("unhealthy_after_periods", &self.unhealthy_after_periods)
+            .field("overloaded_after_overruns", &self.overloaded_after_overruns)
+            .field("max_consecutive_failures", &self.max_consecutive_failures)
+            .field("supervision", &self.supervision)
+            .field("jitter", &self.jitter)
+            .field("resync_schedule", &self.resync_schedule)
+            .field("cleanup_schedule", &self.cleanup_schedule)
+            .field("drain_on_shutdown", &self.drain_on_shutdown)
+            .field("extensions", &self.extensions)
+            .field("concurrency_limit", &self.concurrency_limit)
+            .field("trigger_debounce", &self.trigger_debounce)
+            .field("missed_tick_behavior", &self.missed_tick_behavior)
+            .finish_non_exhaustive()
     }
 }
 
 pub struct ControllerExecutor<E: Error + Sync + Send + 'static> {
     /// Holds the controller we will facilitate executing a control loop around.
     controller: AsyncSafeController<E>,
-    /// Holds the resync period that was retrieved from calling `resync_period` on the given
-    /// controller we facilitate execution of.
-    resync_period: std::time::Duration,
-    /// Closed when the control loop has ended.
-    done_chan: Option<tokio::sync::oneshot::Receiver<()>>,
+    /// Holds the name of the controller, captured up front so logging it never requires locking
+    /// the controller.
+    name: Arc<str>,
+    /// Holds the startup phase of the controller.
+    phase: u32,
+    /// Holds the resync period, which starts out as the one retrieved from calling
+    /// `resync_period` on the given controller we facilitate execution of, and can be changed with
+    /// `set_resync_period`.
+    resync_period: watch::Sender<std::time::Duration>,
+    /// Kept so that the resync period can be changed while no control loop is running. Cloned
+    /// into each control loop, which watches it for changes.
+    resync_periods: watch::Receiver<std::time::Duration>,
+    /// Holds the cleanup period that was retrieved from calling `cleanup_period` on the given
+    /// controller.
+    cleanup_period: std::time::Duration,
+    /// Whether the controller has anything to clean up, as retrieved from calling `has_cleanup`.
+    has_cleanup: bool,
+    /// Holds the configuration the control loop will be run with.
+    config: ExecutorConfig,
+    /// Handed out to anyone who wants to ask the control loop to reconcile early.
+    trigger: Trigger,
+    /// Receives the requests sent through `trigger`. Shared with the running control loop.
+    triggers: Arc<Mutex<mpsc::Receiver<()>>>,
+    /// Updated by the running control loop, and read to report on its health.
+    health: Health,
+    /// When set, receives every error the control loop runs into.
+    errors: Option<mpsc::Sender<ControllerError<E>>>,
+    /// Set while the controller is paused. Shared with the running control loop.
+    paused: Arc<AtomicBool>,
+    /// Set by `finish` to ask the running control loop for a final pass, and reset each time it
+    /// is started.
+    finish: watch::Sender<bool>,
+    /// Kept so that finishing can be requested while no control loop is running. Cloned into
+    /// each control loop, which watches it.
+    finishes: watch::Receiver<bool>,
+    /// Set once the running control loop has initialized the controller.
+    initialized: Initialized,
+    /// Set while the running control loop drains. Shared with the controller.
+    draining: Draining,
+    /// The controllers that must have initialized before this one initializes.
+    dependencies: Vec<Initialized>,
+    /// Set to how the control loop ended, once it has. None until the control loop is started.
+    exit: Option<watch::Receiver<Option<ControllerExit<E>>>>,
 }
 
 impl<E> ControllerExecutor<E>
@@ -100,70 +398,1365 @@ where
     /// Create a new ControllerExecutor. Essentially the same as a From impl. The reason this is a
     /// new constructor is because you can't have an async From impl.
     pub async fn new(controller: Box<dyn Controller<Error = E>>) -> ControllerExecutor<E> {
-        let resync_period = controller.resync_period().await;
+        Self::with_config(controller, ExecutorConfig::default()).await
+    }
+
+    /// Create a new ControllerExecutor that will run its control loop with the given config.
+    pub async fn with_config(
+        mut controller: Box<dyn Controller<Error = E>>,
+        config: ExecutorConfig,
+    ) -> ControllerExecutor<E> {
+        controller.set_event_recorder(config.events.clone());
+        let draining = Draining::default();
+        controller.set_draining(draining.clone());
+        let name: Arc<str> = Arc::from(controller.name());
+        let resync_period = at_least_min_period(&name, "resync", controller.resync_period());
+        let (resync_period_tx, resync_periods) = watch::channel(resync_period);
+        let cleanup_period = at_least_min_period(&name, "cleanup", controller.cleanup_period());
+        let (trigger, triggers) = Trigger::channel();
+        let (finish, finishes) = watch::channel(false);
+        let health = Health::new(
+            name.clone(),
+            resync_period * config.unhealthy_after_periods,
+            config.clock.clone(),
+        );
         ControllerExecutor {
+            name,
+            phase: controller.phase(),
+            has_cleanup: controller.has_cleanup(),
             controller: AsyncSafeController::from(controller),
-            resync_period,
-            done_chan: None,
+            resync_period: resync_period_tx,
+            resync_periods,
+            cleanup_period,
+            config,
+            trigger,
+            triggers: Arc::new(Mutex::new(triggers)),
+            health,
+            errors: None,
+            paused: Arc::new(AtomicBool::new(false)),
+            finish,
+            finishes,
+            initialized: Initialized::default(),
+            draining,
+            dependencies: vec![],
+            exit: None,
+        }
+    }
+
+    /// Returns the name of the controller this executor runs.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the startup phase of the controller this executor runs.
+    pub fn phase(&self) -> u32 {
+        self.phase
+    }
+
+    /// Returns the signal that is set once the control loop has initialized the controller.
+    pub(crate) fn initialized(&self) -> Initialized {
+        self.initialized.clone()
+    }
+
+    /// Sets the controllers that must have initialized before this one is initialized. Takes
+    /// effect the next time `start` is called.
+    pub(crate) fn set_dependencies(&mut self, dependencies: Vec<Initialized>) {
+        self.dependencies = dependencies;
+    }
+
+    /// Records that the control loop's task was aborted, which it had no chance to record itself.
+    pub(crate) fn aborted(&self) {
+        self.initialized.reset();
+        self.health.stopped();
+    }
+
+    /// Returns a snapshot of the control loop's health.
+    pub fn health(&self) -> ControllerHealth {
+        self.health.snapshot()
+    }
+
+    /// Returns the control loop's health, to take snapshots of later.
+    pub(crate) fn health_handle(&self) -> Health {
+        self.health.clone()
+    }
+
+    /// Returns a handle that can be used to make the control loop reconcile before its next
+    /// resync tick.
+    pub fn trigger(&self) -> Trigger {
+        self.trigger.clone()
+    }
+
+    /// Returns the period the control loop resyncs on.
+    pub fn resync_period(&self) -> std::time::Duration {
+        *self.resync_periods.borrow()
+    }
+
+    /// Changes the period the control loop resyncs on, without restarting it. A running control
+    /// loop restarts its resync schedule from the change, so the next tick is a new period later,
+    /// and ticks it would have had under the old period are not made up for. The health check's
+    /// staleness threshold is scaled to the new period too. Periods shorter than `MIN_PERIOD` are
+    /// raised to it.
+    pub fn set_resync_period(&self, resync_period: std::time::Duration) {
+        let resync_period = at_least_min_period(&self.name, "resync", resync_period);
+        self.health
+            .set_max_staleness(resync_period * self.config.unhealthy_after_periods);
+        // Can't fail, since we hold a receiver ourselves.
+        let _ = self.resync_period.send(resync_period);
+    }
+
+    /// Replaces the controller this executor runs with the given one, keeping everything the
+    /// executor holds, such as its health, failure count, triggers and config, without restarting
+    /// the control loop. Anything the old controller holds itself, such as the queue of a `Keyed`
+    /// controller, goes with it.
+    ///
+    /// The new controller is validated and initialized before it is swapped in, and any error
+    /// doing so is returned, leaving the old controller in place. The swap waits for a call into
+    /// the old controller that is underway to finish, so the new one takes over from the next
+    /// reconcile or cleanup. The control loop adopts the new controller's resync period, but keeps
+    /// the old one's name, phase, cleanup period and whether it has anything to clean up, until it
+    /// is started again.
+    pub async fn replace(&self, mut controller: Box<dyn Controller<Error = E>>) -> Result<(), E> {
+        controller.validate()?;
+        controller.set_event_recorder(self.config.events.clone());
+        controller.set_draining(self.draining.clone());
+        controller.initialize().await?;
+        let resync_period = controller.resync_period();
+
+        *self.controller.0.lock().await = controller;
+        info!(controller = %self.name, "Replaced controller");
+        if resync_period != self.resync_period() {
+            self.set_resync_period(resync_period);
+        }
+        Ok(())
+    }
+
+    /// Sets the backoff applied after consecutive reconcile failures. Takes effect the next time
+    /// `start` is called.
+    pub fn set_backoff(&mut self, backoff: Option<BackoffConfig>) {
+        self.config.backoff = backoff;
+    }
+
+    /// Sets the timeout applied to each `reconcile` and `cleanup` call. Takes effect the next time
+    /// `start` is called.
+    pub fn set_reconcile_timeout(&mut self, reconcile_timeout: Option<std::time::Duration>) {
+        self.config.reconcile_timeout = reconcile_timeout;
+    }
+
+    /// Sets the semaphore limiting how many calls into controllers sharing it run at once. See
+    /// `ExecutorConfig::concurrency_limit`. Takes effect the next time `start` is called.
+    pub fn set_concurrency_limit(&mut self, concurrency_limit: Option<Arc<Semaphore>>) {
+        self.config.concurrency_limit = concurrency_limit;
+    }
+
+    /// Pauses the controller, so that its control loop stops calling `reconcile` and `cleanup`
+    /// until it is resumed. Unlike cancelling, the control loop keeps running, so nothing it has
+    /// accumulated is lost.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+        self.health.set_paused(true);
+    }
+
+    /// Resumes a paused controller, which reconciles right away rather than waiting for its next
+    /// resync tick.
+    pub fn resume(&self) {
+        if self.paused.swap(false, Ordering::SeqCst) {
+            self.health.set_paused(false);
+            self.trigger.trigger();
+        }
+    }
+
+    /// Returns whether the controller is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Asks the running control loop to stop once it has run one more full pass, reconciling and
+    /// then cleaning up, so that the latest desired state is flushed before it exits, unlike
+    /// cancelling, which stops it right away. A pass already in progress finishes first, and the
+    /// final pass runs after it. A controller that is still initializing makes its first pass its
+    /// last, and a paused controller stops without one. Returns immediately; use `wait` to wait
+    /// for the control loop to exit, with `ControllerExit::Cancelled` unless the final pass gives
+    /// up on the controller.
+    ///
+    /// Cancelling the control loop's context still stops it right away, final pass or not.
+    pub fn finish(&self) {
+        // Can't fail, since we hold a receiver ourselves.
+        let _ = self.finish.send(true);
+    }
+
+    /// Sets a channel that is sent every error the control loop runs into, along with the phase it
+    /// happened in, so that they can be handled programmatically. Errors are still logged. If the
+    /// channel is full, the error is dropped rather than holding up the control loop. Takes effect
+    /// the next time `start` is called.
+    pub fn set_errors(&mut self, errors: Option<mpsc::Sender<ControllerError<E>>>) {
+        self.errors = errors;
+    }
+
+    /// Waits for the control loop to exit, blocking until it has, and returns how it ended.
+    /// Returns `None` immediately if the control loop was never started. Once it has exited, every
+    /// call returns the same exit until it is started again.
+    ///
+    /// Any number of callers can wait at once, and a wait that is abandoned, such as by a timeout,
+    /// is safe to retry.
+    pub async fn wait(&self) -> Option<ControllerExit<E>> {
+        let mut exit = self.exit.clone()?;
+        loop {
+            if let Some(exit) = &*exit.borrow() {
+                return Some(exit.clone());
+            }
+            if exit.changed().await.is_err() {
+                // The control loop went away without saying how, which only happens if its task
+                // was aborted, such as by the runtime shutting down.
+                return Some(ControllerExit::Cancelled);
+            }
         }
     }
 
-    /// Waits for the control loop to gracefully exit, blocking until it has.
-    pub async fn wait(&mut self) {
-        if let Some(rx) = &mut self.done_chan {
-            rx.await.unwrap();
+    /// Waits for the control loop to initialize the controller, returning how the control loop
+    /// ended instead if it exits first. Returns `Cancelled` immediately if the control loop was
+    /// never started.
+    pub async fn wait_initialized(&self) -> Result<(), ControllerExit<E>> {
+        tokio::select! {
+            biased;
+            _ = self.initialized.wait() => Ok(()),
+            exit = self.wait() => Err(exit.unwrap_or(ControllerExit::Cancelled)),
+        }
+    }
+
+    /// Runs a single pass of the controller, calling `initialize`, then `reconcile`, then
+    /// `cleanup` exactly once each, and returning the first error instead of logging it. This
+    /// suits one-shot CLI commands and cron jobs, where a scheduler owns the cadence, as well as
+    /// testing reconcile logic end to end. `cleanup` is skipped for controllers without any.
+    ///
+    /// If the given context is cancelled, the call in progress is abandoned and the remaining
+    /// calls are skipped, returning `Ok`. Both calls are made as the first `Iteration`.
+    pub async fn run_once(&mut self, mut ctx: Context) -> Result<(), E> {
+        tokio::select! {
+            result = self.controller.initialize() => result?,
+            _ = ctx.done() => return Ok(()),
+        }
+
+        // Neither call is left to finish once the context is cancelled, so their own contexts
+        // never need cancelling.
+        let (call_ctx, _handle) = Context::new();
+        let mut call_ctx = call_context(&self.config, call_ctx, 1);
+        tokio::select! {
+            result = async {
+                let _permit = acquire(&self.config.concurrency_limit).await;
+                self.controller.reconcile(&mut call_ctx).await
+            } => {
+                result?;
+            },
+            _ = ctx.done() => return Ok(()),
+        }
+
+        if !self.has_cleanup {
+            return Ok(());
+        }
+        tokio::select! {
+            result = async {
+                let _permit = acquire(&self.config.concurrency_limit).await;
+                self.controller.cleanup(&mut call_ctx).await
+            } => result,
+            _ = ctx.done() => Ok(()),
         }
     }
 
     /// Begin execution of the concrete control loop that facilitates executing the underlying
-    /// logic of the controller we are an executor for.
-    pub async fn start(&mut self, mut ctx: Context) -> JoinHandle<()> {
-        let mut interval = interval(self.resync_period);
-        let mut controller = self.controller.clone();
-        let (tx, rx) = tokio::sync::oneshot::channel();
-        self.done_chan = Some(rx);
-
-        tokio::task::spawn(async move {
-            loop {
-                if controller.initialize().await.is_ok() {
-                    break;
+    /// logic of the controller we are an executor for. If the control loop stops on its own, it
+    /// is restarted according to `ExecutorConfig::supervision`, until the given context is
+    /// cancelled.
+    pub async fn start(&mut self, ctx: Context) -> JoinHandle<()> {
+        // Can't fail, since we hold a receiver ourselves.
+        let _ = self.finish.send(false);
+        let mut finishes = self.finishes.clone();
+        // Resetting isn't a request to finish, so the control loop shouldn't see it as one.
+        finishes.borrow_and_update();
+        let control_loop = ControlLoop {
+            controller: self.controller.clone(),
+            name: self.name.clone(),
+            resync_periods: self.resync_periods.clone(),
+            cleanup_period: self.cleanup_period,
+            has_cleanup: self.has_cleanup,
+            config: self.config.clone(),
+            triggers: self.triggers.clone(),
+            health: self.health.clone(),
+            errors: self.errors.clone(),
+            paused: self.paused.clone(),
+            finishes,
+            initialized: self.initialized.clone(),
+            draining: self.draining.clone(),
+            dependencies: self.dependencies.clone(),
+            failures: 0,
+            overruns: 0,
+            iteration: 0,
+        };
+        let (tx, rx) = watch::channel(None);
+        self.exit = Some(rx);
+
+        let supervision = self.config.supervision;
+        // Everything logged by the control loop, including by the controller itself, is tagged
+        // with the controller it came from, however it is logged.
+        let span = info_span!("control_loop", controller = %self.name);
+        let supervised = async move {
+            let mut ctx = ctx;
+            let mut finishes = control_loop.finishes.clone();
+            let mut restarts = 0;
+            let exit = loop {
+                let exit = control_loop.fresh().run(&mut ctx).await;
+                if !supervision.should_restart(&exit, restarts) {
+                    break exit;
                 }
 
-                // Wait for the next tick, or until we're told to quit.
+                restarts += 1;
+                let delay = supervision
+                    .backoff
+                    .jittered_delay(restarts, &control_loop.name);
+                warn!(
+                    controller = %control_loop.name,
+                    "Control loop stopped ({}), restarting in {:?}", exit, delay
+                );
                 tokio::select! {
-                    _ = interval.tick() => {
-                        continue;
-                    },
-                    _ = ctx.done() => {
-                        info!("Aborting controller initialization");
-                        return;
+                    _ = control_loop.config.clock.sleep(delay) => control_loop.health.restarted(),
+                    _ = ctx.done() => break ControllerExit::Cancelled,
+                    // A controller waiting to be restarted has nothing in flight to flush.
+                    Ok(()) = finishes.changed() => break ControllerExit::Cancelled,
+                }
+            };
+
+            if !matches!(exit, ControllerExit::Cancelled) {
+                control_loop.health.exited(exit.to_string());
+            }
+            // Nobody may be waiting anymore if the executor was dropped, which is fine.
+            let _ = tx.send(Some(exit));
+        };
+        tokio::task::spawn(supervised.instrument(span))
+    }
+}
+
+/// Holds the state of a single control loop. It is owned by the task spawned in
+/// `ControllerExecutor::start`.
+struct ControlLoop<E: Error + Sync + Send + 'static> {
+    controller: AsyncSafeController<E>,
+    name: Arc<str>,
+    resync_periods: watch::Receiver<std::time::Duration>,
+    cleanup_period: std::time::Duration,
+    has_cleanup: bool,
+    config: ExecutorConfig,
+    triggers: Arc<Mutex<mpsc::Receiver<()>>>,
+    health: Health,
+    errors: Option<mpsc::Sender<ControllerError<E>>>,
+    paused: Arc<AtomicBool>,
+    finishes: watch::Receiver<bool>,
+    initialized: Initialized,
+    draining: Draining,
+    dependencies: Vec<Initialized>,
+    /// The number of consecutive reconcile failures, reset on the first success.
+    failures: u32,
+    /// The number of consecutive reconciles that took longer than the resync period.
+    overruns: u32,
+    /// The number of reconcile passes run so far.
+    iteration: u64,
+}
+
+impl<E> ControlLoop<E>
+where
+    E: Error + Sync + Send + 'static,
+{
+    /// Returns a copy of this control loop that hasn't run yet, to start or restart it with.
+    fn fresh(&self) -> ControlLoop<E> {
+        ControlLoop {
+            controller: self.controller.clone(),
+            name: self.name.clone(),
+            resync_periods: self.resync_periods.clone(),
+            cleanup_period: self.cleanup_period,
+            has_cleanup: self.has_cleanup,
+            config: self.config.clone(),
+            triggers: self.triggers.clone(),
+            health: self.health.clone(),
+            errors: self.errors.clone(),
+            paused: self.paused.clone(),
+            finishes: self.finishes.clone(),
+            initialized: self.initialized.clone(),
+            draining: self.draining.clone(),
+            dependencies: self.dependencies.clone(),
+            failures: 0,
+            overruns: 0,
+            iteration: 0,
+        }
+    }
+
+    /// Runs the control loop to completion, keeping its health up to date, and returns how it
+    /// ended.
+    async fn run(mut self, ctx: &mut Context) -> ControllerExit<E> {
+        self.health.started();
+        self.draining.reset();
+        // Panics within calls into the controller are already caught, so this only catches the
+        // control loop itself panicking, such as in a metrics or event recorder.
+        let exit = match AssertUnwindSafe(self.run_until_cancelled(ctx))
+            .catch_unwind()
+            .await
+        {
+            Ok(exit) => exit,
+            Err(panic) => {
+                let message = panic_message(panic);
+                error!(controller = %self.name, "control loop panicked: {}", message);
+                ControllerExit::Panicked(message)
+            }
+        };
+        self.initialized.reset();
+        self.health.stopped();
+        exit
+    }
+
+    /// Initializes the controller and then drives it until the context is cancelled, or it fails
+    /// too many times in a row.
+    async fn run_until_cancelled(&mut self, ctx: &mut Context) -> ControllerExit<E> {
+        let jitter = self
+            .config
+            .jitter
+            .map(|jitter| Jitter::new(jitter, &self.name));
+        let resync_period = *self.resync_periods.borrow();
+        let mut resync = Schedule::new(
+            self.config.clock.clone(),
+            resync_period,
+            jitter,
+            self.config.resync_schedule.clone(),
+            self.config.missed_tick_behavior,
+        );
+        let mut triggers = self.triggers.clone().lock_owned().await;
+
+        if !self.dependencies.is_empty() {
+            info!(controller = %self.name, "Waiting for controllers in earlier phases to initialize");
+        }
+        for dependency in self.dependencies.iter() {
+            tokio::select! {
+                _ = dependency.wait() => {},
+                _ = ctx.done() => {
+                    info!(controller = %self.name, "Aborting controller initialization");
+                    return ControllerExit::Cancelled;
+                }
+            }
+        }
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let delay = match call(&*self.config.clock, None, self.controller.initialize()).await {
+                Ok(()) => {
+                    self.initialized.set();
+                    self.health.initialized();
+                    break;
+                }
+                Err(e) => {
+                    self.health.initialize_failed(e.to_string());
+                    if self
+                        .config
+                        .max_initialize_attempts
+                        .is_some_and(|max| attempt >= max)
+                    {
+                        error!(
+                            controller = %self.name,
+                            error = %e,
+                            error.source = %SourceChain(&e),
+                            attempt,
+                            "Giving up on initializing controller after {} attempts",
+                            attempt
+                        );
+                        self.record_failure(Phase::Initialize, &e);
+                        return ControllerExit::Failed(Arc::new(e));
                     }
+                    let delay = self
+                        .config
+                        .initialize_backoff
+                        .jittered_delay(attempt, &self.name);
+                    error!(
+                        controller = %self.name,
+                        error = %e,
+                        error.source = %SourceChain(&e),
+                        attempt,
+                        "controller initialize failed, retrying in {:?}",
+                        delay
+                    );
+                    self.report(Phase::Initialize, e);
+                    delay
+                }
+            };
+
+            // Wait out the backoff, or until we're told to quit.
+            tokio::select! {
+                _ = self.config.clock.sleep(delay) => {
+                    continue;
+                },
+                _ = ctx.done() => {
+                    info!(controller = %self.name, "Aborting controller initialization");
+                    return ControllerExit::Cancelled;
                 }
             }
+        }
 
-            info!("Starting control loop");
-            loop {
-                if let Err(e) = controller.reconcile().await {
-                    error!("controller reconcile failed: {}", e);
+        info!(controller = %self.name, "Starting control loop");
+        // The first pass runs right away, so the next resync is a full period after it rather than
+        // wherever initialization left the schedule.
+        resync.reset();
+        // Cleanup runs on its own schedule, independently of when reconciliation runs, unless the
+        // controller has none. Without a cron schedule, the first cleanup runs right away.
+        let mut cleanup_schedule = Schedule::new(
+            self.config.clock.clone(),
+            self.cleanup_period,
+            None,
+            self.config.cleanup_schedule.clone(),
+            self.config.missed_tick_behavior,
+        );
+        if self.config.cleanup_schedule.is_none() {
+            cleanup_schedule.fire_now();
+        }
+        let draining = self.config.drain_on_shutdown.then(|| self.draining.clone());
+        'control: loop {
+            // Finishing asked for before the pass starts makes it the last.
+            let finishing = *self.finishes.borrow_and_update();
+            let (call_ctx, call_handle) = Context::new();
+            let (result, cancelled) =
+                until_cancelled(ctx, draining.as_ref(), call_handle, self.pass(call_ctx)).await;
+            let requeue_after = match result {
+                Some(Err(e)) => {
+                    error!(
+                        controller = %self.name,
+                        error = %e,
+                        error.source = %SourceChain(&e),
+                        consecutive_failures = self.failures,
+                        "Giving up on controller after {} consecutive failures",
+                        self.failures
+                    );
+                    return ControllerExit::Failed(Arc::new(e));
+                }
+                Some(Ok(requeue_after)) if !cancelled => requeue_after,
+                _ => break 'control,
+            };
+            if finishing {
+                if self.has_cleanup {
+                    let (call_ctx, call_handle) = Context::new();
+                    until_cancelled(
+                        ctx,
+                        draining.as_ref(),
+                        call_handle,
+                        self.cleanup_pass(call_ctx),
+                    )
+                    .await;
                 }
+                info!(controller = %self.name, "Finished final pass");
+                break 'control;
+            }
 
-                if let Err(e) = controller.cleanup().await {
-                    error!("controller cleanup failed: {}", e);
+            // While failing with a backoff configured, resync ticks and triggers are ignored so
+            // that the backoff delay is what paces the next attempt.
+            let backoff_delay = self
+                .config
+                .backoff
+                .filter(|_| self.failures > 0)
+                .map(|backoff| backoff.jittered_delay(self.failures, &self.name));
+            let backing_off = backoff_delay.is_some();
+            let clock = self.config.clock.clone();
+            let wake = async move {
+                match backoff_delay.or(requeue_after) {
+                    Some(delay) => clock.sleep(delay).await,
+                    None => pending().await,
                 }
+            };
+            tokio::pin!(wake);
+            // Set by the first trigger while triggers are debounced, and completes once the
+            // debounce window has passed. Triggers in the meantime join the same pass.
+            let mut debounce: Option<BoxFuture<'static, ()>> = None;
 
+            // Wait for the next reason to reconcile, running cleanup whenever it comes due in the
+            // meantime.
+            loop {
                 tokio::select! {
-                    _ = interval.tick() => {
-                        continue;
+                    _ = resync.tick(), if !backing_off => {
+                        break;
                     },
-                    _ = ctx.done() => {
+                    _ = &mut wake => {
+                        if backing_off {
+                            // Restart the resync schedule so ticks missed while backing off don't
+                            // all fire at once.
+                            resync.reset();
+                        }
+                        break;
+                    },
+                    Some(()) = triggers.recv(), if !backing_off => {
+                        let window = self.config.trigger_debounce;
+                        if window.is_zero() {
+                            break;
+                        }
+                        if debounce.is_none() {
+                            debug!(controller = %self.name, "Debouncing triggers for {:?}", window);
+                            debounce = Some(self.config.clock.sleep(window));
+                        }
+                    },
+                    _ = async { debounce.as_mut().unwrap().await }, if debounce.is_some() => {
+                        break;
+                    },
+                    _ = cleanup_schedule.tick(), if self.has_cleanup => {
+                        let (call_ctx, call_handle) = Context::new();
+                        let (_, cancelled) = until_cancelled(
+                            ctx,
+                            draining.as_ref(),
+                            call_handle,
+                            self.cleanup_pass(call_ctx),
+                        )
+                        .await;
+                        if cancelled {
+                            break 'control;
+                        }
+                    },
+                    Ok(()) = self.finishes.changed() => {
                         break;
+                    },
+                    Ok(()) = self.resync_periods.changed() => {
+                        let resync_period = *self.resync_periods.borrow();
+                        info!(controller = %self.name, "Resync period changed to {:?}", resync_period);
+                        resync.set_period(resync_period);
+                    },
+                    _ = ctx.done() => {
+                        break 'control;
                     }
                 }
             }
+        }
+
+        info!(controller = %self.name, "Control loop terminated");
+        ControllerExit::Cancelled
+    }
+
+    /// Runs a single reconcile pass of the control loop inside of a span tagged with the
+    /// iteration, within the control loop's span tagged with the controller name, returning how
+    /// soon the controller asked to be requeued, if at all. See `reconcile`.
+    async fn pass(&mut self, ctx: Context) -> Result<Option<std::time::Duration>, CallError<E>> {
+        if self.paused.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+
+        self.iteration += 1;
+        let span = info_span!(
+            "reconcile",
+            iteration = self.iteration,
+            elapsed_ms = field::Empty,
+        );
+
+        let started = self.config.clock.now();
+        let requeue_after = self.reconcile(ctx).instrument(span.clone()).await;
+
+        let elapsed_ms = (self.config.clock.now() - started).as_millis() as u64;
+        span.record("elapsed_ms", elapsed_ms);
+        span.in_scope(|| debug!(elapsed_ms, "Reconcile pass finished"));
+
+        requeue_after
+    }
+
+    /// Reconciles the controller, tracking consecutive failures, and returns how soon the
+    /// controller asked to be requeued, if at all. Returns the error once the controller has
+    /// failed `max_consecutive_failures` times in a row, rather than reporting it to the error
+    /// channel, since the control loop exits with it.
+    async fn reconcile(
+        &mut self,
+        ctx: Context,
+    ) -> Result<Option<std::time::Duration>, CallError<E>> {
+        let permit = acquire(&self.config.concurrency_limit).await;
+        let limit = self.config.reconcile_timeout;
+        let started = self.config.clock.now();
+        let mut ctx = self.call_context(ctx, started);
+        let result = call(
+            &*self.config.clock,
+            limit,
+            self.controller.reconcile(&mut ctx),
+        )
+        .await;
+        drop(permit);
+        let elapsed = self.config.clock.now() - started;
+        self.config.metrics.record_reconcile(
+            &self.name,
+            elapsed,
+            result.as_ref().map(|_| ()).map_err(|_| ()),
+        );
+        self.check_overrun(elapsed);
+        let stats = ctx.stats();
+        self.config
+            .metrics
+            .record_reconcile_items(&self.name, stats);
+
+        match result {
+            Ok(outcome) => {
+                self.failures = 0;
+                self.health.succeeded(stats);
+                Ok(match outcome {
+                    ReconcileOutcome::Done => None,
+                    ReconcileOutcome::Requeue => Some(std::time::Duration::from_secs(0)),
+                    ReconcileOutcome::RequeueAfter(delay) => Some(delay),
+                })
+            }
+            Err(e) => {
+                self.failures = self.failures.saturating_add(1);
+                self.health.failed(e.to_string());
+                error!(
+                    controller = %self.name,
+                    error = %e,
+                    error.source = %SourceChain(&e),
+                    consecutive_failures = self.failures,
+                    "controller reconcile failed"
+                );
+                if self
+                    .config
+                    .max_consecutive_failures
+                    .is_some_and(|max| self.failures >= max)
+                {
+                    self.record_failure(Phase::Reconcile, &e);
+                    return Err(e);
+                }
+                self.report(Phase::Reconcile, e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Counts reconciles in a row that took longer than the resync period, reporting the
+    /// controller as overloaded once there have been `overloaded_after_overruns` of them.
+    fn check_overrun(&mut self, elapsed: std::time::Duration) {
+        let threshold = self.config.overloaded_after_overruns;
+        if threshold == 0 {
+            return;
+        }
+        let resync_period = *self.resync_periods.borrow();
+        if elapsed <= resync_period {
+            if self.overruns >= threshold {
+                info!(controller = %self.name, "Reconcile finished within its resync period again");
+                self.health.set_overloaded(false);
+            }
+            self.overruns = 0;
+            return;
+        }
+
+        self.overruns = self.overruns.saturating_add(1);
+        if self.overruns == threshold {
+            warn!(
+                controller = %self.name,
+                "The last {} reconciles each took longer than the resync period of {:?}, most \
+                 recently {:?}; consider a longer resync period or reconciling concurrently",
+                self.overruns,
+                resync_period,
+                elapsed
+            );
+            self.health.set_overloaded(true);
+        }
+    }
+
+    /// Runs a single cleanup pass of the control loop inside of its own span, within the control
+    /// loop's span tagged with the controller name.
+    async fn cleanup_pass(&mut self, ctx: Context) {
+        if self.paused.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let span = info_span!("cleanup");
+        self.cleanup(ctx).instrument(span).await;
+    }
+
+    /// Cleans up after the controller, logging any failure.
+    async fn cleanup(&mut self, ctx: Context) {
+        let permit = acquire(&self.config.concurrency_limit).await;
+        let limit = self.config.reconcile_timeout;
+        let started = self.config.clock.now();
+        let mut ctx = self.call_context(ctx, started);
+        let result = call(
+            &*self.config.clock,
+            limit,
+            self.controller.cleanup(&mut ctx),
+        )
+        .await;
+        drop(permit);
+        self.config.metrics.record_cleanup(
+            &self.name,
+            self.config.clock.now() - started,
+            result.as_ref().map(|_| ()).map_err(|_| ()),
+        );
+
+        if let Err(e) = result {
+            error!(
+                controller = %self.name,
+                error = %e,
+                error.source = %SourceChain(&e),
+                "controller cleanup failed"
+            );
+            self.report(Phase::Cleanup, e);
+        }
+    }
+
+    /// Returns the ReconcileContext for a call starting at the given time, cancelled along with
+    /// the given Context. Cleanups share the iteration of the reconcile pass before them.
+    fn call_context(&self, ctx: Context, started: Instant) -> ReconcileContext {
+        let mut ctx = call_context(&self.config, ctx, self.iteration);
+        if let Some(limit) = self.config.reconcile_timeout {
+            ctx.extensions_mut().insert(Deadline(started + limit));
+        }
+        ctx
+    }
+
+    /// Records a failure event for an error, and forwards it to the error channel, if one was set.
+    fn report(&self, phase: Phase, error: CallError<E>) {
+        self.record_failure(phase, &error);
+
+        if let Some(errors) = &self.errors {
+            let error = ControllerError {
+                controller: self.name.to_string(),
+                phase,
+                error,
+            };
+            if errors.try_send(error).is_err() {
+                debug!(controller = %self.name, "error channel full or closed, dropping {} error", phase);
+            }
+        }
+    }
+
+    /// Records a failure event for an error.
+    fn record_failure(&self, phase: Phase, error: &CallError<E>) {
+        self.config.events.event(
+            &self.name,
+            phase.failure_reason(),
+            &format!("controller {} failed: {}", phase, error),
+            EventType::Warning,
+        );
+    }
+}
+
+/// Set once a control loop has initialized its controller, and reset once it terminates, so that
+/// controllers in later phases can wait for it.
+#[derive(Clone, Default)]
+pub(crate) struct Initialized(Arc<InitializedInner>);
+
+#[derive(Default)]
+struct InitializedInner {
+    set: AtomicBool,
+    notify: Notify,
+}
+
+impl Initialized {
+    fn set(&self) {
+        self.0.set.store(true, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+    }
+
+    fn reset(&self) {
+        self.0.set.store(false, Ordering::SeqCst);
+    }
+
+    /// Waits until the signal is set.
+    async fn wait(&self) {
+        loop {
+            // Created before checking the flag so that a notification sent in between isn't
+            // missed.
+            let notified = self.0.notify.notified();
+            if self.0.set.load(Ordering::SeqCst) {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Set while a control loop drains, after being cancelled with `ExecutorConfig::drain_on_shutdown`,
+/// and reset once it is started again. Handed to each controller with `Controller::set_draining`.
+#[derive(Debug, Clone, Default)]
+pub struct Draining(Arc<AtomicBool>);
+
+impl Draining {
+    /// Returns whether the control loop is draining, in which case the controller should finish
+    /// the work it has in hand without starting on anything new.
+    pub fn is_draining(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn set(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Drives a reconcile or cleanup pass until it finishes, or the context is cancelled. A cancelled
+/// pass is abandoned, unless draining is given, in which case it is set and the pass is left to
+/// finish. Either way, the pass's own context is cancelled through the given handle. Returns the
+/// pass's output, if it finished, and whether the context was cancelled.
+async fn until_cancelled<T, F>(
+    ctx: &mut Context,
+    draining: Option<&Draining>,
+    handle: Handle,
+    fut: F,
+) -> (Option<T>, bool)
+where
+    F: Future<Output = T>,
+{
+    tokio::pin!(fut);
+    tokio::select! {
+        output = &mut fut => (Some(output), false),
+        _ = ctx.done() => {
+            handle.cancel();
+            match draining {
+                Some(draining) => {
+                    draining.set();
+                    (Some(fut.await), true)
+                }
+                None => (None, true),
+            }
+        },
+    }
+}
+
+/// Returns the given period, raised to `MIN_PERIOD` with a warning if it is shorter.
+fn at_least_min_period(name: &str, kind: &str, period: std::time::Duration) -> std::time::Duration {
+    if period >= MIN_PERIOD {
+        return period;
+    }
+    warn!(
+        controller = %name,
+        "{} period {:?} is shorter than {:?}, using {:?} instead", kind, period, MIN_PERIOD, MIN_PERIOD
+    );
+    MIN_PERIOD
+}
+
+/// Waits for a permit from the given concurrency limit, if there is one.
+async fn acquire(limit: &Option<Arc<Semaphore>>) -> Option<OwnedSemaphorePermit> {
+    match limit {
+        // The semaphore is never closed, so acquiring it can't fail.
+        Some(semaphore) => semaphore.clone().acquire_owned().await.ok(),
+        None => None,
+    }
+}
+
+/// Returns the ReconcileContext for a call in the given iteration, carrying the config's
+/// extensions.
+fn call_context(config: &ExecutorConfig, ctx: Context, iteration: u64) -> ReconcileContext {
+    let mut ctx = ReconcileContext::with_extensions(ctx, config.extensions.clone());
+    ctx.extensions_mut().insert(Iteration(iteration));
+    ctx
+}
+
+/// Schedules resyncs every period, optionally randomizing each period with jitter, or at the times
+/// a cron schedule matches. Unlike a tokio interval, the first tick is a full period away.
+struct Schedule {
+    clock: Arc<dyn Clock>,
+    period: std::time::Duration,
+    jitter: Option<Jitter>,
+    /// When set, ticks follow this rather than the period.
+    cron: Option<CronSchedule>,
+    /// How ticks that were missed are made up for.
+    missed_ticks: MissedTickBehavior,
+    /// The wall clock time at a point on the clock, which cron schedules are matched against.
+    /// Fixed when the schedule is created, so that the cron schedule follows the clock.
+    anchor: (Instant, SystemTime),
+    next: Instant,
+}
+
+impl Schedule {
+    fn new(
+        clock: Arc<dyn Clock>,
+        period: std::time::Duration,
+        jitter: Option<Jitter>,
+        cron: Option<CronSchedule>,
+        missed_ticks: MissedTickBehavior,
+    ) -> Schedule {
+        let now = clock.now();
+        let mut schedule = Schedule {
+            next: now,
+            clock,
+            period,
+            jitter,
+            cron,
+            missed_ticks,
+            anchor: (now, SystemTime::now()),
+        };
+        schedule.reset();
+        schedule
+    }
+
+    /// Restarts the schedule, so that the next tick is a period from now, or the next time the
+    /// cron schedule matches.
+    fn reset(&mut self) {
+        let now = self.clock.now();
+        self.next = self.after(now);
+    }
+
+    /// Makes the next tick fire right away.
+    fn fire_now(&mut self) {
+        self.next = self.clock.now();
+    }
+
+    /// Changes the period, restarting the schedule so that the next tick is the new period from
+    /// now. Has no effect on when a cron schedule ticks.
+    fn set_period(&mut self, period: std::time::Duration) {
+        self.period = period;
+        if self.cron.is_none() {
+            self.reset();
+        }
+    }
+
+    /// Waits for the next tick. Ticks that were missed are made up for according to the
+    /// schedule's MissedTickBehavior.
+    async fn tick(&mut self) {
+        // Only advance once the tick has fired, so that a tick abandoned by a select isn't lost.
+        self.clock.sleep_until(self.next).await;
+        let now = self.clock.now();
+        self.next = match self.missed_ticks {
+            MissedTickBehavior::Burst => self.after(self.next),
+            MissedTickBehavior::Delay => self.after(now),
+            MissedTickBehavior::Skip => {
+                let mut next = self.after(self.next);
+                while next <= now {
+                    next = self.after(next);
+                }
+                next
+            }
+        };
+    }
+
+    /// Returns when the tick after one at the given time is due.
+    fn after(&mut self, tick: Instant) -> Instant {
+        if let Some(cron) = &self.cron {
+            // Cron schedules are matched against the wall clock, so translate the tick to wall
+            // clock time and back.
+            let (at, wall_at) = self.anchor;
+            let wall = wall_at + tick.saturating_duration_since(at);
+            if let Some(next) = cron.next_after(wall) {
+                return tick + next.duration_since(wall).unwrap_or_default();
+            }
+        }
+        tick + self.next_period()
+    }
+
+    fn next_period(&mut self) -> std::time::Duration {
+        match self.jitter.as_mut() {
+            Some(jitter) => jitter.apply(self.period),
+            None => self.period,
+        }
+    }
+}
+
+/// The phase of the control loop a controller error happened in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// A call to `Controller::initialize`.
+    Initialize,
+    /// A call to `Controller::reconcile`.
+    Reconcile,
+    /// A call to `Controller::cleanup`.
+    Cleanup,
+}
+
+impl Phase {
+    /// Returns the reason a failure in this phase is recorded as an event with.
+    fn failure_reason(self) -> &'static str {
+        match self {
+            Phase::Initialize => "InitializeFailed",
+            Phase::Reconcile => "ReconcileFailed",
+            Phase::Cleanup => "CleanupFailed",
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Phase::Initialize => write!(f, "initialize"),
+            Phase::Reconcile => write!(f, "reconcile"),
+            Phase::Cleanup => write!(f, "cleanup"),
+        }
+    }
+}
+
+/// An error a control loop ran into, sent to the channel set with
+/// `ControllerExecutor::set_errors`.
+#[derive(Debug)]
+pub struct ControllerError<E> {
+    /// The name of the controller.
+    pub controller: String,
+    /// The phase of the control loop the error happened in.
+    pub phase: Phase,
+    /// How the call into the controller failed.
+    pub error: CallError<E>,
+}
+
+impl<E: fmt::Display> fmt::Display for ControllerError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "controller {} {} failed: {}",
+            self.controller, self.phase, self.error
+        )
+    }
+}
+
+impl<E: Error + 'static> Error for ControllerError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// How a control loop ended, returned by `ControllerExecutor::wait`.
+#[derive(Debug)]
+pub enum ControllerExit<E> {
+    /// The control loop's context was cancelled, and it stopped gracefully.
+    Cancelled,
+    /// The controller failed `ExecutorConfig::max_consecutive_failures` reconciles in a row, or
+    /// `ExecutorConfig::max_initialize_attempts` attempts to initialize, and the control loop gave
+    /// up on it. Holds the last failure, shared so that every waiter can
+    /// see it.
+    Failed(Arc<CallError<E>>),
+    /// The control loop itself panicked, outside of any call into the controller. Holds the panic
+    /// message.
+    Panicked(String),
+}
+
+impl<E> Clone for ControllerExit<E> {
+    fn clone(&self) -> Self {
+        match self {
+            ControllerExit::Cancelled => ControllerExit::Cancelled,
+            ControllerExit::Failed(e) => ControllerExit::Failed(e.clone()),
+            ControllerExit::Panicked(message) => ControllerExit::Panicked(message.clone()),
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for ControllerExit<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControllerExit::Cancelled => write!(f, "cancelled"),
+            ControllerExit::Failed(e) => write!(f, "gave up after repeated failures: {}", e),
+            ControllerExit::Panicked(message) => write!(f, "control loop panicked: {}", message),
+        }
+    }
+}
+
+/// The ways a single call into a controller can fail.
+#[derive(Debug)]
+pub enum CallError<E> {
+    /// The controller returned an error.
+    Failed(E),
+    /// The call did not complete within the configured time limit.
+    TimedOut(std::time::Duration),
+    /// The controller panicked. Holds the panic message, if it had one.
+    Panicked(String),
+}
+
+impl<E: fmt::Display> fmt::Display for CallError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Failed(e) => write!(f, "{}", e),
+            CallError::TimedOut(limit) => write!(f, "timed out after {:?}", limit),
+            CallError::Panicked(message) => write!(f, "panicked: {}", message),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for CallError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CallError::Failed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Awaits a call into a controller, giving up once the optional time limit has elapsed on the
+/// given clock, and catching any panic so that it can't take down the control loop.
+async fn call<T, E, F>(
+    clock: &dyn Clock,
+    limit: Option<std::time::Duration>,
+    fut: F,
+) -> Result<T, CallError<E>>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    // The controller is only ever touched through its mutex, which doesn't poison, so it's safe
+    // to keep using it after a panic.
+    let fut = AssertUnwindSafe(fut).catch_unwind();
+    let result = match limit {
+        Some(limit) => tokio::select! {
+            result = fut => result,
+            _ = clock.sleep(limit) => return Err(CallError::TimedOut(limit)),
+        },
+        None => fut.await,
+    };
+
+    match result {
+        Ok(result) => result.map_err(CallError::Failed),
+        Err(panic) => Err(CallError::Panicked(panic_message(panic))),
+    }
+}
+
+/// Displays the messages of an error's sources, from its immediate source down, joined by ": ",
+/// for an `error.source` log field next to the error itself. Displays nothing if it has no
+/// source. A source displayed just like the error wrapping it, as a CallError displays the
+/// controller's error, adds nothing and is skipped.
+pub(crate) struct SourceChain<'a>(pub(crate) &'a (dyn Error + 'static));
+
+impl fmt::Display for SourceChain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut previous = self.0.to_string();
+        let mut first = true;
+        let mut source = self.0.source();
+        while let Some(e) = source {
+            let message = e.to_string();
+            if message != previous {
+                if !first {
+                    f.write_str(": ")?;
+                }
+                f.write_str(&message)?;
+                first = false;
+            }
+            previous = message;
+            source = e.source();
+        }
+        Ok(())
+    }
+}
+
+/// Extracts the message from a panic payload, which is usually a `&str` or `String`.
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic payload".to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    /// Counts its reconciles, which always succeed.
+    struct Counting {
+        reconciles: Arc<AtomicU32>,
+        resync_period: Duration,
+    }
+
+    #[async_trait]
+    impl Controller for Counting {
+        type Error = std::io::Error;
+
+        async fn reconcile(
+            &mut self,
+            _ctx: &mut ReconcileContext,
+        ) -> Result<ReconcileOutcome, Self::Error> {
+            self.reconciles.fetch_add(1, Ordering::SeqCst);
+            Ok(ReconcileOutcome::Done)
+        }
+
+        fn resync_period(&self) -> Duration {
+            self.resync_period
+        }
+
+        fn has_cleanup(&self) -> bool {
+            false
+        }
+    }
+
+    /// Returns an executor running a Counting controller on the given clock, along with its count
+    /// of reconciles.
+    async fn counting(
+        resync_period: Duration,
+        clock: &ManualClock,
+    ) -> (ControllerExecutor<std::io::Error>, Arc<AtomicU32>) {
+        let reconciles = Arc::new(AtomicU32::new(0));
+        let controller = Counting {
+            reconciles: reconciles.clone(),
+            resync_period,
+        };
+        let config = ExecutorConfig {
+            clock: Arc::new(clock.clone()),
+            ..Default::default()
+        };
+        let executor = ControllerExecutor::with_config(Box::new(controller), config).await;
+        (executor, reconciles)
+    }
+
+    /// Lets the control loop run until it is waiting on the clock again.
+    async fn settle() {
+        for _ in 0..20 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn reconciles_once_per_resync_period() {
+        let clock = ManualClock::new();
+        let (mut executor, reconciles) = counting(Duration::from_secs(10), &clock).await;
+        let (ctx, handle) = Context::new();
+        let task = executor.start(ctx).await;
+        settle().await;
+        // The first pass runs right away.
+        assert_eq!(reconciles.load(Ordering::SeqCst), 1);
+
+        for period in 1..=5 {
+            clock.advance(Duration::from_secs(9));
+            settle().await;
+            assert_eq!(reconciles.load(Ordering::SeqCst), period);
+            clock.advance(Duration::from_secs(1));
+            settle().await;
+            assert_eq!(reconciles.load(Ordering::SeqCst), period + 1);
+        }
+
+        handle.cancel();
+        task.await.unwrap();
+        assert!(matches!(
+            executor.wait().await,
+            Some(ControllerExit::Cancelled)
+        ));
+    }
+
+    #[tokio::test]
+    async fn zero_periods_are_raised_rather_than_panicking() {
+        let clock = ManualClock::new();
+        let (mut executor, reconciles) = counting(Duration::from_secs(0), &clock).await;
+        assert_eq!(executor.resync_period(), MIN_PERIOD);
+        let (ctx, handle) = Context::new();
+        let task = executor.start(ctx).await;
+        settle().await;
+
+        for _ in 0..3 {
+            clock.advance(MIN_PERIOD);
+            settle().await;
+        }
+        assert_eq!(reconciles.load(Ordering::SeqCst), 4);
+
+        handle.cancel();
+        task.await.unwrap();
+        assert!(matches!(
+            executor.wait().await,
+            Some(ControllerExit::Cancelled)
+        ));
+    }
+
+    #[tokio::test]
+    async fn dropping_the_executor_mid_run_doesnt_panic() {
+        let clock = ManualClock::new();
+        let (mut executor, reconciles) = counting(Duration::from_secs(10), &clock).await;
+        let (ctx, handle) = Context::new();
+        let task = executor.start(ctx).await;
+        settle().await;
+        drop(executor);
 
-            info!("Control loop terminated");
+        // The control loop carries on without the executor, and reports its exit to nobody.
+        clock.advance(Duration::from_secs(10));
+        settle().await;
+        assert_eq!(reconciles.load(Ordering::SeqCst), 2);
 
-            tx.send(()).unwrap();
-        })
+        handle.cancel();
+        task.await.unwrap();
     }
 }
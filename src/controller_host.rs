@@ -1,18 +1,111 @@
-use futures::future::join_all;
+use futures::future::{join_all, try_join_all};
 use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{
+    sync::{mpsc, watch, Semaphore},
+    task::JoinHandle,
+    time::timeout,
+};
 use tokio_context::context::{Context, Handle};
+use tracing::{info, warn};
+#[cfg(feature = "admin")]
+use {std::net::SocketAddr, tokio::net::TcpListener};
 
-use crate::controller::{Controller, ControllerExecutor};
+#[cfg(feature = "admin")]
+use crate::admin::{self, PrometheusMetrics};
+use crate::builder::{ControllerHostBuilder, ControllerRegistration};
+use crate::controller::{
+    Controller, ControllerError, ControllerExecutor, ControllerExit, ExecutorConfig, Initialized,
+};
+use crate::health::{ControllerHealth, Health, HealthRegistry, ProbeReport};
+use crate::signals;
+use crate::trigger::Trigger;
 
 /// ControllerHost will facilitate registering controllers by wrapping them in ControllerExecutors,
 /// and beginning asynchronous execution of all ControllerExecutors, which in turn run their
 /// respect controller logic.
 ///
 /// Use `run` to run all of the registered controllers. Use `cancel_all` to cancel all controllers,
-/// and wait on all controllers to finish gracefully executing.
+/// and wait on all controllers to finish gracefully executing. Use `cancel` to stop a single
+/// controller while leaving the rest running, or `remove` to take it out of the host altogether.
+/// Or use `into_running` to hand the host over to a RunningHost, which can be stored and shut
+/// down later.
+///
+/// Each controller is supervised according to its `ExecutorConfig::supervision`, so that one whose
+/// control loop stops on its own can be restarted rather than left dead.
 pub struct ControllerHost<E: Error + Send + Sync + 'static> {
-    executors: Vec<ControllerExecutor<E>>,
+    executors: Vec<HostedExecutor<E>>,
+    /// The health of each executor, kept in step with `executors`.
+    health: HealthRegistry,
+    /// The config used for controllers added with `add_controller`.
+    defaults: ExecutorConfig,
+    /// When set, receives every error any of the controllers run into.
+    errors: Option<mpsc::Sender<ControllerError<E>>>,
+    /// When set, shared by every controller to limit how many calls into them run at once.
+    concurrency_limit: Option<Arc<Semaphore>>,
+    /// Set between `run` and `cancel_all`, so controllers added in the meantime can be started
+    /// right away, and so the host isn't started twice.
+    running: bool,
+    /// When set, bounds how long `run_until_signal` waits for controllers to stop.
+    shutdown_timeout: Option<Duration>,
+}
+
+/// An executor registered with a ControllerHost, along with the handle that cancels its control
+/// loop while it is running. Each executor gets its own handle so it can be stopped on its own.
+struct HostedExecutor<E: Error + Send + Sync + 'static> {
+    executor: ControllerExecutor<E>,
     cancel_handle: Option<Handle>,
+    /// The task running the control loop, to abort it with if it doesn't stop when cancelled.
+    task: Option<JoinHandle<()>>,
+}
+
+impl<E> HostedExecutor<E>
+where
+    E: Error + Send + Sync + 'static,
+{
+    /// Starts the executor's control loop under a fresh context.
+    async fn start(&mut self) {
+        let (ctx, handle) = Context::new();
+        self.task = Some(self.executor.start(ctx).await);
+        self.cancel_handle = Some(handle);
+    }
+
+    /// Cancels the executor's control loop and waits for it to gracefully terminate, returning
+    /// how it ended. Does nothing and returns `None` if it isn't running.
+    async fn cancel(&mut self) -> Option<ControllerExit<E>> {
+        let handle = self.cancel_handle.take()?;
+        handle.cancel();
+        self.executor.wait().await
+    }
+
+    /// Asks the executor's control loop to run a final pass and stop, and waits for it to do so,
+    /// returning how it ended. Does nothing and returns `None` if it isn't running.
+    async fn finish(&mut self) -> Option<ControllerExit<E>> {
+        // Held until the control loop exits, since dropping it would cancel the final pass.
+        let _handle = self.cancel_handle.take()?;
+        self.executor.finish();
+        self.executor.wait().await
+    }
+
+    /// Cancels the executor's control loop and waits up to the given deadline for it to
+    /// gracefully terminate, aborting its task if it doesn't. Returns whether it terminated in
+    /// time, or `None` if it isn't running.
+    async fn cancel_timeout(&mut self, deadline: Duration) -> Option<bool> {
+        let handle = self.cancel_handle.take()?;
+        handle.cancel();
+        let task = self.task.take();
+        if timeout(deadline, self.executor.wait()).await.is_ok() {
+            return Some(true);
+        }
+        if let Some(task) = task {
+            task.abort();
+        }
+        self.executor.aborted();
+        Some(false)
+    }
 }
 
 impl<E> ControllerHost<E>
@@ -21,39 +114,502 @@ where
 {
     /// Create a new ControllerHost.
     pub fn new() -> ControllerHost<E> {
+        Self::with_config(ExecutorConfig::default())
+    }
+
+    /// Create a new ControllerHost which runs controllers added with `add_controller` using the
+    /// given config. This is where to plug in metrics shared by every controller in the host.
+    pub fn with_config(defaults: ExecutorConfig) -> ControllerHost<E> {
         ControllerHost {
             executors: vec![],
-            cancel_handle: None,
+            health: HealthRegistry::default(),
+            defaults,
+            errors: None,
+            concurrency_limit: None,
+            running: false,
+            shutdown_timeout: None,
+        }
+    }
+
+    /// Returns a ControllerHostBuilder, to configure a host and the controllers it runs in one
+    /// place.
+    pub fn builder() -> ControllerHostBuilder<E> {
+        ControllerHostBuilder::new()
+    }
+
+    /// Sets a channel that is sent every error any of the controllers run into. See
+    /// `ControllerExecutor::set_errors`. Takes effect for running controllers the next time they
+    /// are started.
+    pub fn set_errors(&mut self, errors: Option<mpsc::Sender<ControllerError<E>>>) {
+        for hosted in self.executors.iter_mut() {
+            hosted.executor.set_errors(errors.clone());
+        }
+        self.errors = errors;
+    }
+
+    /// Limits how many controllers across the whole host are inside `reconcile` or `cleanup` at
+    /// once, whatever config they were added with, so that controllers which tick together don't
+    /// exhaust a connection pool they share. Each call waits for a free slot before it starts.
+    /// `None` lifts the limit. This is independent of any concurrency within a controller, such as
+    /// `Keyed` workers, which share their controller's single slot. See
+    /// `ExecutorConfig::concurrency_limit`.
+    ///
+    /// Takes effect for running controllers the next time they are started.
+    pub fn set_max_concurrent_reconciles(&mut self, max_concurrent_reconciles: Option<usize>) {
+        let concurrency_limit = max_concurrent_reconciles.map(|max| Arc::new(Semaphore::new(max)));
+        for hosted in self.executors.iter_mut() {
+            hosted
+                .executor
+                .set_concurrency_limit(concurrency_limit.clone());
         }
+        self.concurrency_limit = concurrency_limit;
+    }
+
+    /// Bounds how long `run_until_signal` waits for controllers to stop once the process is asked
+    /// to shut down, stopping them with `cancel_all_timeout` rather than `cancel_all`. `None`
+    /// waits for as long as they take.
+    pub fn set_shutdown_timeout(&mut self, shutdown_timeout: Option<Duration>) {
+        self.shutdown_timeout = shutdown_timeout;
     }
 
     /// Cancels all running executors, and blocks, waiting for them all to gracefully terminate.
-    pub async fn cancel_all(&mut self) {
-        if let Some(handle) = self.cancel_handle.take() {
-            handle.cancel();
-            join_all(self.executors.iter_mut().map(|e| e.wait())).await;
+    ///
+    /// Returns how each controller's control loop ended, in the order they were added, or `None`
+    /// for any that wasn't running. A controller that had already given up on its own, rather
+    /// than being cancelled, reports that here, so a supervisor can decide whether to restart it.
+    pub async fn cancel_all(&mut self) -> Vec<Option<ControllerExit<E>>> {
+        self.running = false;
+        join_all(self.executors.iter_mut().map(|e| e.cancel())).await
+    }
+
+    /// Stops all running executors like `cancel_all`, but only once each has run one final pass,
+    /// reconciling and then cleaning up, so that the latest desired state is flushed before they
+    /// exit, such as when draining before a planned deploy. A pass already in progress finishes
+    /// first. See `ControllerExecutor::finish`.
+    ///
+    /// Blocks until every controller has stopped, and returns how each one ended, in the order
+    /// they were added, or `None` for any that wasn't running. Abandoning the wait, such as with a
+    /// timeout in case a final pass takes too long, cancels every controller still running.
+    pub async fn finish_and_stop(&mut self) -> Vec<Option<ControllerExit<E>>> {
+        self.running = false;
+        join_all(self.executors.iter_mut().map(|e| e.finish())).await
+    }
+
+    /// Cancels all running executors, and waits up to the given deadline for them all to
+    /// gracefully terminate. Any controller that has not stopped by then, such as one stuck
+    /// mid-reconcile, is logged and has its task aborted, so that shutdown can finish before an
+    /// orchestrator's grace period runs out. This bounds controllers that drain on shutdown too,
+    /// with `ExecutorConfig::drain_on_shutdown`.
+    ///
+    /// Aborting a task drops whatever call into the controller it was stuck in the next time that
+    /// call yields, so it can't stop a controller that blocks its thread without ever yielding.
+    ///
+    /// Returns which running controllers stopped within the deadline, and which were aborted.
+    pub async fn cancel_all_timeout(&mut self, deadline: Duration) -> ShutdownReport {
+        self.running = false;
+        let stopped = join_all(
+            self.executors
+                .iter_mut()
+                .map(|e| e.cancel_timeout(deadline)),
+        )
+        .await;
+
+        let mut report = ShutdownReport::default();
+        for (hosted, stopped) in self.executors.iter().zip(stopped) {
+            let name = hosted.executor.name().to_string();
+            match stopped {
+                Some(true) => report.stopped.push(name),
+                Some(false) => {
+                    warn!(
+                        controller = %name,
+                        "controller did not stop within {:?}, aborting it", deadline
+                    );
+                    report.aborted.push(name);
+                }
+                None => {}
+            }
         }
+        report
+    }
+
+    /// Cancels the running executor for the controller with the given name, and blocks, waiting
+    /// for it to gracefully terminate. All other controllers keep running. If several controllers
+    /// share the name, all of them are cancelled.
+    ///
+    /// Returns whether a controller with the given name was registered.
+    pub async fn cancel(&mut self, name: &str) -> bool {
+        let mut found = false;
+        for hosted in self.executors.iter_mut() {
+            if hosted.executor.name() == name {
+                found = true;
+                hosted.cancel().await;
+            }
+        }
+        found
+    }
+
+    /// Cancels the controller with the given name like `cancel`, waiting for it to gracefully
+    /// terminate, and then drops it from the host, leaving every other controller running. If
+    /// several controllers share the name, all of them are removed.
+    ///
+    /// Returns an error if no controller with the given name was registered.
+    pub async fn remove(&mut self, name: &str) -> Result<(), UnknownController> {
+        if !self.cancel(name).await {
+            return Err(UnknownController {
+                name: name.to_string(),
+            });
+        }
+        self.executors.retain(|e| e.executor.name() != name);
+        self.health
+            .write()
+            .unwrap()
+            .retain(|health| health.name() != name);
+        info!(controller = %name, "Removed controller");
+        Ok(())
+    }
+
+    /// Pauses the controller with the given name, so that it stops reconciling while staying
+    /// registered. See `ControllerExecutor::pause`. If several controllers share the name, all of
+    /// them are paused.
+    ///
+    /// Returns whether a controller with the given name was registered.
+    pub fn pause(&self, name: &str) -> bool {
+        self.for_each_named(name, |executor| executor.pause())
+    }
+
+    /// Resumes the paused controller with the given name. If several controllers share the name,
+    /// all of them are resumed.
+    ///
+    /// Returns whether a controller with the given name was registered.
+    pub fn resume(&self, name: &str) -> bool {
+        self.for_each_named(name, |executor| executor.resume())
+    }
+
+    /// Asks the controller with the given name to reconcile as soon as possible, like its
+    /// Trigger. See `Trigger::trigger`. If several controllers share the name, all of them are
+    /// triggered.
+    ///
+    /// Returns an error if no controller with the given name was registered.
+    pub fn trigger(&self, name: &str) -> Result<(), UnknownController> {
+        if self.for_each_named(name, |executor| executor.trigger().trigger()) {
+            Ok(())
+        } else {
+            Err(UnknownController {
+                name: name.to_string(),
+            })
+        }
+    }
+
+    /// Changes the resync period of the controller with the given name, without restarting it.
+    /// See `ControllerExecutor::set_resync_period`. If several controllers share the name, all of
+    /// them are changed.
+    ///
+    /// Returns whether a controller with the given name was registered.
+    pub fn set_resync_period(&self, name: &str, resync_period: Duration) -> bool {
+        self.for_each_named(name, |executor| executor.set_resync_period(resync_period))
+    }
+
+    /// Replaces the controller with the given name with the given controller, while its control
+    /// loop keeps running. See `ControllerExecutor::replace`. If several controllers share the
+    /// name, only the first one added is replaced.
+    ///
+    /// Returns whether a controller with the given name was registered, or the error validating or
+    /// initializing the new controller, in which case the old one is left in place.
+    pub async fn replace(
+        &self,
+        name: &str,
+        controller: Box<dyn Controller<Error = E>>,
+    ) -> Result<bool, E> {
+        match self.executors.iter().find(|e| e.executor.name() == name) {
+            Some(hosted) => hosted.executor.replace(controller).await.map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Calls the given function with every executor whose controller has the given name,
+    /// returning whether there were any.
+    fn for_each_named(&self, name: &str, f: impl Fn(&ControllerExecutor<E>)) -> bool {
+        let mut found = false;
+        for hosted in self.executors.iter() {
+            if hosted.executor.name() == name {
+                found = true;
+                f(&hosted.executor);
+            }
+        }
+        found
+    }
+
+    /// Returns a snapshot of the health of every registered controller, in the order they were
+    /// added.
+    pub fn health(&self) -> Vec<ControllerHealth> {
+        self.health
+            .read()
+            .unwrap()
+            .iter()
+            .map(Health::snapshot)
+            .collect()
+    }
+
+    /// Checks that no controller's control loop has stopped on its own for good, which is the
+    /// host's liveness. Controllers that haven't been started yet, or were cancelled, pass it, so
+    /// that a standby replica under `run_while_leader` isn't restarted. See
+    /// `ControllerHealth::exit_error`.
+    pub fn liveness(&self) -> ProbeReport {
+        ProbeReport::liveness(&self.health())
+    }
+
+    /// Checks whether every controller is ready and healthy, which is the host's readiness. See
+    /// `ControllerHealth::ready`.
+    pub fn readiness(&self) -> ProbeReport {
+        ProbeReport::readiness(&self.health())
+    }
+
+    /// Returns whether the host is live. See `liveness` for why it isn't.
+    pub fn is_live(&self) -> bool {
+        self.liveness().passed()
+    }
+
+    /// Returns whether the host is ready. See `readiness` for why it isn't.
+    pub fn is_ready(&self) -> bool {
+        self.readiness().passed()
+    }
+
+    /// Returns a snapshot of the health of the controller with the given name, or `None` if no
+    /// such controller is registered. If several controllers share the name, the first one added
+    /// is reported.
+    pub fn health_of(&self, name: &str) -> Option<ControllerHealth> {
+        self.executors
+            .iter()
+            .find(|e| e.executor.name() == name)
+            .map(|e| e.executor.health())
+    }
+
+    /// Serves the admin HTTP endpoint on the given address, reporting on every registered
+    /// controller, including those added or removed later, and on the given metrics, if any. See the `admin` module for what it
+    /// serves. Enabled by the `admin` feature.
+    ///
+    /// Returns the task serving the endpoint, which runs until it is aborted, or an error if the
+    /// address could not be bound.
+    #[cfg(feature = "admin")]
+    pub async fn serve_admin(
+        &self,
+        addr: SocketAddr,
+        metrics: Option<Arc<PrometheusMetrics>>,
+    ) -> io::Result<JoinHandle<()>> {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Serving admin endpoint on {}", listener.local_addr()?);
+        Ok(admin::serve(listener, self.health.clone(), metrics))
     }
 
     /// Starts all controllers up, returning immediately. Call `cancel_all` to cancel all
-    /// executors, which will also block, waiting for all executors to gracefully exit. Do not use
-    /// `run` again until you have run both `cancel_all`.
-    pub async fn run(&mut self) {
-        let (_, mut handle) = Context::new();
-        join_all(self.executors.iter_mut().map(|e| {
-            let ctx = handle.spawn_ctx();
-            e.start(ctx)
-        }))
-        .await;
+    /// executors, which will also block, waiting for all executors to gracefully exit.
+    ///
+    /// Controllers are initialized in order of their `phase`. Every control loop starts right
+    /// away, but a controller only initializes once all controllers in earlier phases have
+    /// initialized. Cancelling a controller that is still waiting its turn aborts it as usual.
+    ///
+    /// Returns an error, leaving the running controllers as they are, if the host is already
+    /// running, until it has been stopped again with `cancel_all` or one of its variants.
+    pub async fn run(&mut self) -> Result<(), AlreadyRunning> {
+        if self.running {
+            return Err(AlreadyRunning);
+        }
+        self.start_all().await;
+        Ok(())
+    }
 
-        self.cancel_handle = Some(handle);
+    /// Starts all controllers up like `run`, unless the host is already running, in which case
+    /// it is left as it is.
+    async fn start_all(&mut self) {
+        if self.running {
+            return;
+        }
+        for i in 0..self.executors.len() {
+            let dependencies = self.dependencies(self.executors[i].executor.phase());
+            self.executors[i].executor.set_dependencies(dependencies);
+        }
+        join_all(self.executors.iter_mut().map(|e| e.start())).await;
+        self.running = true;
     }
 
-    /// Adds a controller to the host. All controllers that have been added to the host will
-    /// automatically have their control loops started when `run` is executed.
-    pub async fn add_controller(&mut self, controller: Box<dyn Controller<Error = E>>) {
+    /// Starts all controllers up like `run`, unless the host is already running, then waits until
+    /// every one of them has been initialized, leaving them running. This is the place to do anything that should only
+    /// happen once the whole host is up, such as registering with service discovery. Controllers
+    /// in later phases are only initialized once earlier ones have been, so this waits for the
+    /// last phase.
+    ///
+    /// A controller that fails to initialize is retried as usual, so this keeps waiting. If a
+    /// control loop stops before its controller has been initialized instead, such as by
+    /// panicking, every controller is cancelled with `cancel_all`, and how that control loop ended
+    /// is returned. Abandoning the wait, such as with a timeout, leaves the controllers running as
+    /// after `run`.
+    pub async fn run_and_await_ready(&mut self) -> Result<(), ControllerExit<E>> {
+        self.start_all().await;
+        let initialized = self.executors.iter().map(|e| e.executor.wait_initialized());
+        if let Err(exit) = try_join_all(initialized).await {
+            self.cancel_all().await;
+            return Err(exit);
+        }
+        info!("All controllers initialized");
+        Ok(())
+    }
+
+    /// Starts all controllers up like `run`, unless the host is already running, then blocks until
+    /// the process is asked to shut down, as described by `signals::shutdown`, at which point all
+    /// controllers are cancelled with `cancel_all`, or with `cancel_all_timeout` if a shutdown
+    /// timeout has been set with `set_shutdown_timeout`.
+    ///
+    /// Returns an error if the signal handlers could not be installed, once the controllers have
+    /// been cancelled again.
+    pub async fn run_until_signal(&mut self) -> io::Result<()> {
+        self.start_all().await;
+        let result = signals::shutdown().await;
+        match self.shutdown_timeout {
+            Some(deadline) => {
+                self.cancel_all_timeout(deadline).await;
+            }
+            None => {
+                self.cancel_all().await;
+            }
+        }
+        result
+    }
+
+    /// Starts all controllers up like `run`, unless the host is already running, and hands them
+    /// over to the returned RunningHost, which shuts them down again with `shutdown`.
+    pub async fn into_running(mut self) -> RunningHost<E> {
+        self.start_all().await;
+        RunningHost { host: self }
+    }
+
+    /// Returns the signals of every controller in a phase earlier than the given one.
+    fn dependencies(&self, phase: u32) -> Vec<Initialized> {
         self.executors
-            .push(ControllerExecutor::new(controller).await);
+            .iter()
+            .filter(|e| e.executor.phase() < phase)
+            .map(|e| e.executor.initialized())
+            .collect()
+    }
+
+    /// Runs all controllers only while this instance holds leadership, as reported by the given
+    /// receiver, such as the one returned by `LeaderElector::start`. Controllers are started
+    /// whenever leadership is gained, and cancelled whenever it is lost. Blocks until the given
+    /// context is cancelled, or the sender of leadership is dropped, at which point all
+    /// controllers are cancelled.
+    pub async fn run_while_leader(
+        &mut self,
+        mut leadership: watch::Receiver<bool>,
+        mut ctx: Context,
+    ) {
+        loop {
+            while !*leadership.borrow() {
+                tokio::select! {
+                    changed = leadership.changed() => if changed.is_err() { return },
+                    _ = ctx.done() => return,
+                }
+            }
+
+            info!("Leadership acquired, starting controllers");
+            self.start_all().await;
+
+            let stop = loop {
+                tokio::select! {
+                    changed = leadership.changed() => match changed {
+                        Ok(()) if *leadership.borrow() => continue,
+                        Ok(()) => break false,
+                        Err(_) => break true,
+                    },
+                    _ = ctx.done() => break true,
+                }
+            };
+
+            if stop {
+                self.cancel_all().await;
+                return;
+            }
+
+            info!("Leadership lost, cancelling controllers");
+            self.cancel_all().await;
+        }
+    }
+
+    /// Runs a single pass of every registered controller concurrently, returning each one's
+    /// result in the order they were added. See `ControllerExecutor::run_once`. Cancelling the
+    /// given context cancels every pass still in progress.
+    pub async fn run_once_all(&mut self, mut ctx: Context) -> Vec<Result<(), E>> {
+        let (contexts, handles): (Vec<Context>, Vec<Handle>) =
+            self.executors.iter().map(|_| Context::new()).unzip();
+        let runs = join_all(
+            self.executors
+                .iter_mut()
+                .zip(contexts)
+                .map(|(e, ctx)| e.executor.run_once(ctx)),
+        );
+        tokio::pin!(runs);
+
+        tokio::select! {
+            results = &mut runs => results,
+            _ = ctx.done() => {
+                for handle in handles {
+                    handle.cancel();
+                }
+                runs.await
+            }
+        }
+    }
+
+    /// Adds a controller to the host. All controllers that have been added to the host will
+    /// automatically have their control loops started when `run` is executed. If the host is
+    /// already running, the controller's control loop is started immediately, and will be
+    /// cancelled along with the rest by `cancel_all`.
+    ///
+    /// Accepts a boxed controller, or a ControllerBuilder to configure how the controller is run,
+    /// which takes precedence over the host's config.
+    ///
+    /// Returns a Trigger that can be used to make the controller reconcile before its next resync
+    /// tick, or the error `Controller::validate` rejected the controller with, in which case it
+    /// isn't added.
+    pub async fn add_controller(
+        &mut self,
+        controller: impl Into<ControllerRegistration<E>>,
+    ) -> Result<Trigger, E> {
+        let config = self.defaults.clone();
+        self.add_controller_with_config(controller, config).await
+    }
+
+    /// Adds a controller to the host whose control loop will be run with the given config, such
+    /// as a backoff to apply after consecutive reconcile failures. Anything configured on a
+    /// ControllerBuilder takes precedence over the given config. See `add_controller`.
+    pub async fn add_controller_with_config(
+        &mut self,
+        controller: impl Into<ControllerRegistration<E>>,
+        config: ExecutorConfig,
+    ) -> Result<Trigger, E> {
+        let registration = controller.into();
+        registration.controller.validate()?;
+        let config = registration.overrides.apply(config);
+        let mut executor = ControllerExecutor::with_config(registration.controller, config).await;
+        executor.set_errors(self.errors.clone());
+        if self.concurrency_limit.is_some() {
+            executor.set_concurrency_limit(self.concurrency_limit.clone());
+        }
+        let trigger = executor.trigger();
+        let mut hosted = HostedExecutor {
+            executor,
+            cancel_handle: None,
+            task: None,
+        };
+        if self.running {
+            let dependencies = self.dependencies(hosted.executor.phase());
+            hosted.executor.set_dependencies(dependencies);
+            hosted.start().await;
+        }
+        self.health
+            .write()
+            .unwrap()
+            .push(hosted.executor.health_handle());
+        self.executors.push(hosted);
+        Ok(trigger)
     }
 }
 
@@ -65,3 +621,119 @@ where
         Self::new()
     }
 }
+
+/// A ControllerHost whose controllers have all been started, returned by
+/// `ControllerHost::into_running`. It owns the controllers and the handles that cancel them, so it
+/// can be stored in a larger service without borrowing the host, and shut down once the service
+/// is done with it.
+///
+/// Dropping a RunningHost without shutting it down cancels every controller without waiting for
+/// them to gracefully terminate.
+pub struct RunningHost<E: Error + Send + Sync + 'static> {
+    host: ControllerHost<E>,
+}
+
+impl<E> RunningHost<E>
+where
+    E: Error + Send + Sync + 'static,
+{
+    /// Returns a snapshot of the health of every controller. See `ControllerHost::health`.
+    pub fn health(&self) -> Vec<ControllerHealth> {
+        self.host.health()
+    }
+
+    /// Checks the host's liveness. See `ControllerHost::liveness`.
+    pub fn liveness(&self) -> ProbeReport {
+        self.host.liveness()
+    }
+
+    /// Checks the host's readiness. See `ControllerHost::readiness`.
+    pub fn readiness(&self) -> ProbeReport {
+        self.host.readiness()
+    }
+
+    /// Returns a snapshot of the health of the controller with the given name. See
+    /// `ControllerHost::health_of`.
+    pub fn health_of(&self, name: &str) -> Option<ControllerHealth> {
+        self.host.health_of(name)
+    }
+
+    /// Pauses the controller with the given name. See `ControllerHost::pause`.
+    pub fn pause(&self, name: &str) -> bool {
+        self.host.pause(name)
+    }
+
+    /// Resumes the paused controller with the given name. See `ControllerHost::resume`.
+    pub fn resume(&self, name: &str) -> bool {
+        self.host.resume(name)
+    }
+
+    /// Asks the controller with the given name to reconcile. See `ControllerHost::trigger`.
+    pub fn trigger(&self, name: &str) -> Result<(), UnknownController> {
+        self.host.trigger(name)
+    }
+
+    /// Cancels every controller, and blocks, waiting for them all to gracefully terminate.
+    /// Returns how each one ended. See `ControllerHost::cancel_all`.
+    pub async fn shutdown(mut self) -> Vec<Option<ControllerExit<E>>> {
+        self.host.cancel_all().await
+    }
+
+    /// Stops every controller once each has run one final pass, and blocks, waiting for them all
+    /// to terminate. Returns how each one ended. See `ControllerHost::finish_and_stop`.
+    pub async fn finish_and_stop(mut self) -> Vec<Option<ControllerExit<E>>> {
+        self.host.finish_and_stop().await
+    }
+
+    /// Cancels every controller, and waits up to the given deadline for them all to gracefully
+    /// terminate. See `ControllerHost::cancel_all_timeout`.
+    ///
+    /// Returns which controllers stopped within the deadline, and which were aborted.
+    pub async fn shutdown_timeout(mut self, deadline: Duration) -> ShutdownReport {
+        self.host.cancel_all_timeout(deadline).await
+    }
+}
+
+/// How the controllers stopped by `ControllerHost::cancel_all_timeout` ended, by name, in the
+/// order they were added. Controllers that weren't running are left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// The controllers that gracefully terminated within the deadline.
+    pub stopped: Vec<String>,
+    /// The controllers that were still running at the deadline, and so were aborted.
+    pub aborted: Vec<String>,
+}
+
+impl ShutdownReport {
+    /// Returns whether every controller gracefully terminated within the deadline.
+    pub fn is_clean(&self) -> bool {
+        self.aborted.is_empty()
+    }
+}
+
+/// No controller with the given name is registered with the host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownController {
+    name: String,
+}
+
+impl fmt::Display for UnknownController {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no controller named {:?} is registered", self.name)
+    }
+}
+
+impl Error for UnknownController {}
+
+/// The host was asked to `run` while it was already running. Running it again would start a
+/// second control loop for every controller, fighting the first over the same controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlreadyRunning;
+
+impl fmt::Display for AlreadyRunning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the controller host is already running")
+    }
+}
+
+impl Error for AlreadyRunning {}
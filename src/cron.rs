@@ -0,0 +1,232 @@
+//! Parses cron expressions, so that controllers can reconcile or clean up at set times of day
+//! rather than on a fixed period.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The number of days in 400 years, after which the calendar repeats itself. A schedule that
+/// doesn't match within that long never will.
+const DAYS_PER_CYCLE: u64 = 146_097;
+
+/// A schedule described by a standard five field cron expression, such as `0 2 * * *` for 2am
+/// every day. Times are matched in UTC, to the minute.
+///
+/// The fields are, in order, the minute (0-59), hour (0-23), day of the month (1-31), month
+/// (1-12) and day of the week (0-7, where both 0 and 7 are Sunday). Each field is `*`, a value, a
+/// range like `1-5`, or a comma separated list of those, and any of them but a single value can
+/// be followed by a step like `*/15`. As in most crons, when both the day of the month and the day
+/// of the week are restricted, a day matching either one matches.
+///
+/// `@yearly`, `@monthly`, `@weekly`, `@daily` and `@hourly` are accepted as shorthands too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    /// Whether the day of the month field was `*`, rather than restricting the days.
+    any_day_of_month: bool,
+    /// Whether the day of the week field was `*`, rather than restricting the days.
+    any_day_of_week: bool,
+}
+
+impl CronSchedule {
+    /// Parses the given cron expression. Returns an error if it is malformed, or never matches
+    /// any time at all, like `0 0 30 2 *`.
+    pub fn parse(expression: &str) -> Result<CronSchedule, CronError> {
+        let invalid = |reason: String| CronError {
+            expression: expression.to_string(),
+            reason,
+        };
+
+        let expanded = match expression.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(invalid(format!(
+                "expected 5 fields, found {}",
+                fields.len()
+            )));
+        }
+
+        let field = |index: usize, name: &str, min: u32, max: u32| {
+            parse_field(fields[index], min, max)
+                .map_err(|reason| invalid(format!("{}: {}", name, reason)))
+        };
+        let mut days_of_week = field(4, "day of week", 0, 7)?;
+        // Sunday can be written as either 0 or 7.
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week | 1) & !(1 << 7);
+        }
+        let schedule = CronSchedule {
+            expression: expression.to_string(),
+            minutes: field(0, "minute", 0, 59)?,
+            hours: field(1, "hour", 0, 23)?,
+            days_of_month: field(2, "day of month", 1, 31)?,
+            months: field(3, "month", 1, 12)?,
+            days_of_week,
+            any_day_of_month: fields[2].starts_with('*'),
+            any_day_of_week: fields[4].starts_with('*'),
+        };
+
+        if schedule.next_after(UNIX_EPOCH).is_none() {
+            return Err(invalid("never matches any date".to_string()));
+        }
+        Ok(schedule)
+    }
+
+    /// Returns the expression this schedule was parsed from.
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    /// Returns the first time the schedule matches strictly after the given time, or `None` if
+    /// there is none, such as for times before the Unix epoch.
+    pub fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        let since_epoch = after.duration_since(UNIX_EPOCH).ok()?;
+        // The next whole minute strictly after the given time.
+        let start = since_epoch.as_secs() / 60 + 1;
+        let (start_day, start_minute) = (start / 1440, start % 1440);
+
+        for day in start_day..start_day + DAYS_PER_CYCLE {
+            if !self.matches_day(day) {
+                continue;
+            }
+            let first = if day == start_day { start_minute } else { 0 };
+            for minute_of_day in first..1440 {
+                let (hour, minute) = (minute_of_day / 60, minute_of_day % 60);
+                if self.hours & (1 << hour) != 0 && self.minutes & (1 << minute) != 0 {
+                    let minutes = day * 1440 + minute_of_day;
+                    return Some(UNIX_EPOCH + Duration::from_secs(minutes * 60));
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns whether the schedule matches the given day, counted in days since the Unix epoch.
+    fn matches_day(&self, day: u64) -> bool {
+        let (month, day_of_month) = month_and_day(day);
+        if self.months & (1 << month) == 0 {
+            return false;
+        }
+        // The epoch was a Thursday.
+        let day_of_week = (day + 4) % 7;
+        let dom = self.days_of_month & (1 << day_of_month) != 0;
+        let dow = self.days_of_week & (1 << day_of_week) != 0;
+        if self.any_day_of_month || self.any_day_of_week {
+            dom && dow
+        } else {
+            dom || dow
+        }
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = CronError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        CronSchedule::parse(expression)
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+/// A cron expression that could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronError {
+    expression: String,
+    reason: String,
+}
+
+impl fmt::Display for CronError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid cron expression {:?}: {}",
+            self.expression, self.reason
+        )
+    }
+}
+
+impl Error for CronError {}
+
+/// Parses a single field of a cron expression into a bitmask of the values it matches.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let parse_value = |value: &str| -> Result<u32, String> {
+        let value: u32 = value
+            .parse()
+            .map_err(|_| format!("{:?} is not a number", value))?;
+        if value < min || value > max {
+            return Err(format!("{} is outside {}-{}", value, min, max));
+        }
+        Ok(value)
+    };
+
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("{:?} is not a valid step", step))?;
+                (range, Some(step))
+            }
+            None => (part, None),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (parse_value(start)?, parse_value(end)?),
+                // A single value with a step, like `5/15`, runs to the end of the range.
+                None if step.is_some() => (parse_value(range)?, max),
+                None => {
+                    let value = parse_value(range)?;
+                    (value, value)
+                }
+            },
+        };
+        if start > end {
+            return Err(format!("{}-{} is backwards", start, end));
+        }
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+/// Returns the month (1-12) and day of the month (1-31) of the given day, counted in days since
+/// the Unix epoch. See http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+fn month_and_day(day: u64) -> (u32, u32) {
+    // Shift the epoch to 0000-03-01, so that leap days fall at the end of each year.
+    let shifted = day + 719_468;
+    let day_of_era = shifted % DAYS_PER_CYCLE;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day_of_month = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    (month as u32, day_of_month as u32)
+}
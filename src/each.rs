@@ -0,0 +1,89 @@
+//! Helpers for working through many items in a single reconcile or cleanup pass, without one item
+//! that fails holding up all of those after it.
+//!
+//! Returning early with `?` from a loop over items leaves every item after a failing one
+//! untouched until the next pass, when the same item likely fails first again. `for_each_item`
+//! carries on past failures instead, and reports all of them once every item has been tried.
+
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+
+/// Runs the given function on every item in turn, carrying on past any it fails for. Returns
+/// every error once all of the items have been tried, or `Ok` if none failed.
+///
+/// Controllers returning their own error type can convert the ItemErrors into it, or just take
+/// its first error with `into_first`.
+pub async fn for_each_item<T, E, F, Fut>(
+    items: impl IntoIterator<Item = T>,
+    mut f: F,
+) -> Result<(), ItemErrors<E>>
+where
+    F: FnMut(T) -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+{
+    let mut attempted = 0;
+    let mut errors = Vec::new();
+    for item in items {
+        attempted += 1;
+        if let Err(e) = f(item).await {
+            errors.push(e);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ItemErrors { errors, attempted })
+    }
+}
+
+/// The errors `for_each_item` ran into, in the order of the items that failed. Holds at least
+/// one.
+#[derive(Debug)]
+pub struct ItemErrors<E> {
+    errors: Vec<E>,
+    /// The number of items tried, including those that succeeded.
+    attempted: usize,
+}
+
+impl<E> ItemErrors<E> {
+    /// Returns every error, in the order of the items that failed.
+    pub fn errors(&self) -> &[E] {
+        &self.errors
+    }
+
+    /// Returns the number of items that were tried, including those that succeeded.
+    pub fn attempted(&self) -> usize {
+        self.attempted
+    }
+
+    /// Returns every error, in the order of the items that failed.
+    pub fn into_errors(self) -> Vec<E> {
+        self.errors
+    }
+
+    /// Returns the error of the first item that failed, discarding the rest.
+    pub fn into_first(self) -> E {
+        // There is always at least one error, or for_each_item would have returned Ok.
+        self.errors.into_iter().next().unwrap()
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for ItemErrors<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} items failed, first with: {}",
+            self.errors.len(),
+            self.attempted,
+            self.errors[0]
+        )
+    }
+}
+
+impl<E: Error + 'static> Error for ItemErrors<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.errors[0])
+    }
+}
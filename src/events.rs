@@ -0,0 +1,55 @@
+//! A hook for recording structured events about the items a controller reconciles, so that they
+//! can be persisted or surfaced to users rather than only logged.
+
+use std::fmt;
+
+use tracing::{info, warn};
+
+/// Whether an event is routine, or something someone may need to look into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventType {
+    /// Something expected happened, such as a status being created.
+    Normal,
+    /// Something went wrong, such as a reconcile failing.
+    Warning,
+}
+
+impl fmt::Display for EventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventType::Normal => write!(f, "Normal"),
+            EventType::Warning => write!(f, "Warning"),
+        }
+    }
+}
+
+/// Receives the events controllers record about the items they reconcile, much like Kubernetes
+/// events. Implement this to persist events, for example into a table users can query, without
+/// this crate depending on any particular store.
+///
+/// The control loop hands each controller the recorder it was configured with through
+/// `Controller::set_event_recorder`, and records a `Warning` event of its own whenever a call
+/// into the controller fails, keyed by the controller's name.
+pub trait EventRecorder: Send + Sync {
+    /// Records a single event about the item identified by `key`. The reason is a short, machine
+    /// readable description of what happened, like `StatusCreated`, and the message a human
+    /// readable one.
+    ///
+    /// This is called from the control loop, so it shouldn't block. Recorders that write events
+    /// somewhere slow should hand them off, for example to a spawned task.
+    fn event(&self, key: &str, reason: &str, message: &str, event_type: EventType);
+}
+
+/// An EventRecorder that logs every event, at info level for `Normal` events and warn level for
+/// `Warning` ones. Used when no recorder is configured.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoggingEventRecorder;
+
+impl EventRecorder for LoggingEventRecorder {
+    fn event(&self, key: &str, reason: &str, message: &str, event_type: EventType) {
+        match event_type {
+            EventType::Normal => info!(key, reason, "{}", message),
+            EventType::Warning => warn!(key, reason, "{}", message),
+        }
+    }
+}
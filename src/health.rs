@@ -0,0 +1,333 @@
+//! Tracks whether control loops are alive and reconciling successfully.
+
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use tokio::time::Instant;
+
+use crate::clock::Clock;
+use crate::context::ReconcileStats;
+
+/// A snapshot of how a single controller's control loop is doing.
+#[derive(Debug, Clone)]
+pub struct ControllerHealth {
+    /// The name of the controller.
+    pub name: String,
+    /// Whether the control loop is currently running.
+    pub running: bool,
+    /// Why the control loop stopped on its own for good, if it did: it gave up on the controller
+    /// after repeated failures, or panicked, and wasn't restarted. This is what fails the
+    /// controller's liveness, unlike a control loop the host cancelled. Cleared once the control
+    /// loop is started again.
+    pub exit_error: Option<String>,
+    /// Whether the control loop is still initializing the controller, is running it, or has
+    /// stopped.
+    pub state: LoopState,
+    /// Whether the controller has reconciled successfully since its control loop last started,
+    /// and so has caught up with its specs. This is the controller's readiness, which unlike
+    /// `healthy` doesn't lapse once it has been reached, until the control loop stops. A `Keyed`
+    /// controller's pass only succeeds once it has worked through the keys it queued.
+    pub ready: bool,
+    /// Whether the controller is paused. A paused controller isn't reconciling, but is still
+    /// considered healthy.
+    pub paused: bool,
+    /// When the controller last reconciled successfully, if ever.
+    pub last_success: Option<SystemTime>,
+    /// The most recent error, if the last reconcile failed, or the controller failed to initialize
+    /// and hasn't reconciled successfully since.
+    pub last_error: Option<String>,
+    /// When the most recent error happened, if there is one.
+    pub last_error_at: Option<SystemTime>,
+    /// How many reconciles in a row have failed since the last success, or since the control
+    /// loop last started.
+    pub consecutive_failures: u32,
+    /// How many items the last successful reconcile processed and skipped, if there has been one.
+    pub last_pass: Option<ReconcileStats>,
+    /// Whether the controller's recent reconciles have each taken longer than its resync period,
+    /// leaving it permanently behind. See `ExecutorConfig::overloaded_after_overruns`.
+    pub overloaded: bool,
+    /// The number of times the control loop has been restarted after stopping on its own. See
+    /// `ExecutorConfig::supervision`.
+    pub restarts: u32,
+    /// Whether the controller is running and has reconciled successfully recently enough. A
+    /// controller that has just started is given the same grace period to succeed for the first
+    /// time.
+    pub healthy: bool,
+}
+
+/// The outcome of a liveness or readiness probe across every controller in a host, from
+/// `ControllerHost::liveness` or `ControllerHost::readiness`. Explains why the probe failed, so
+/// that a handler serving it, in whichever web framework, can say so alongside its 503.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProbeReport {
+    /// Every controller that failed the probe, in the order they were added.
+    pub failing: Vec<ProbeFailure>,
+}
+
+/// Why a single controller failed a probe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeFailure {
+    /// The name of the controller.
+    pub name: String,
+    /// Why the controller failed the probe.
+    pub reason: String,
+}
+
+impl ProbeReport {
+    /// Checks each controller's liveness, which it has unless its control loop stopped on its own
+    /// for good. A control loop that was never started, or was cancelled, such as on a standby
+    /// replica, is still live.
+    pub(crate) fn liveness(health: &[ControllerHealth]) -> ProbeReport {
+        ProbeReport::check(health, |health| {
+            health
+                .exit_error
+                .as_ref()
+                .map(|exit| format!("control loop stopped unexpectedly: {}", exit))
+        })
+    }
+
+    /// Checks each controller's readiness, which it has once it is `ready` and while it is
+    /// `healthy`.
+    pub(crate) fn readiness(health: &[ControllerHealth]) -> ProbeReport {
+        ProbeReport::check(health, |health| {
+            let reason = if !health.running {
+                "control loop is not running"
+            } else if health.state == LoopState::Initializing {
+                "controller has not been initialized yet"
+            } else if !health.ready {
+                "controller has not reconciled successfully since starting"
+            } else if !health.healthy {
+                "controller has not reconciled successfully recently"
+            } else {
+                return None;
+            };
+            Some(match &health.last_error {
+                Some(error) => format!("{}: {}", reason, error),
+                None => reason.to_string(),
+            })
+        })
+    }
+
+    /// Collects the controllers the given check returns a reason to fail for.
+    fn check(
+        health: &[ControllerHealth],
+        reason: impl Fn(&ControllerHealth) -> Option<String>,
+    ) -> ProbeReport {
+        let failing = health
+            .iter()
+            .filter_map(|health| {
+                reason(health).map(|reason| ProbeFailure {
+                    name: health.name.clone(),
+                    reason,
+                })
+            })
+            .collect();
+        ProbeReport { failing }
+    }
+
+    /// Returns whether every controller passed the probe.
+    pub fn passed(&self) -> bool {
+        self.failing.is_empty()
+    }
+}
+
+impl fmt::Display for ProbeFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.reason)
+    }
+}
+
+/// Where a control loop is in its lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopState {
+    /// The control loop is waiting for the controllers it depends on, or for its own controller
+    /// to be initialized.
+    Initializing,
+    /// The controller has been initialized, and the control loop is driving it.
+    Running,
+    /// The control loop isn't running, because it was never started or has stopped.
+    Stopped,
+}
+
+impl LoopState {
+    /// Returns the name of the state, as reported by the admin endpoint.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoopState::Initializing => "initializing",
+            LoopState::Running => "running",
+            LoopState::Stopped => "stopped",
+        }
+    }
+}
+
+/// The health of every controller registered with a host, in the order they were added. Shared
+/// with the admin endpoint, so that it reports on controllers added or removed after it started.
+pub(crate) type HealthRegistry = Arc<RwLock<Vec<Health>>>;
+
+/// The health of a control loop, shared between the executor and its running task so it can be
+/// read without locking the controller.
+#[derive(Clone)]
+pub(crate) struct Health {
+    name: Arc<str>,
+    clock: Arc<dyn Clock>,
+    state: Arc<Mutex<HealthState>>,
+}
+
+struct HealthState {
+    /// How long the controller may go without a successful reconcile before it is unhealthy.
+    max_staleness: Duration,
+    running: bool,
+    exit_error: Option<String>,
+    /// Whether the controller has been initialized since the control loop last started.
+    initialized: bool,
+    paused: bool,
+    /// When the control loop was last started or resumed, used as the baseline before the next
+    /// success.
+    started: Option<Instant>,
+    last_success: Option<(Instant, SystemTime)>,
+    last_error: Option<(String, SystemTime)>,
+    consecutive_failures: u32,
+    last_pass: Option<ReconcileStats>,
+    /// Whether a reconcile has succeeded since the control loop last started.
+    ready: bool,
+    overloaded: bool,
+    restarts: u32,
+}
+
+impl Health {
+    /// Create a new Health for the named controller, which becomes unhealthy once it goes longer
+    /// than `max_staleness` on the given clock without a successful reconcile.
+    pub(crate) fn new(name: Arc<str>, max_staleness: Duration, clock: Arc<dyn Clock>) -> Health {
+        Health {
+            name,
+            clock,
+            state: Arc::new(Mutex::new(HealthState {
+                max_staleness,
+                running: false,
+                exit_error: None,
+                initialized: false,
+                paused: false,
+                started: None,
+                last_success: None,
+                last_error: None,
+                consecutive_failures: 0,
+                last_pass: None,
+                ready: false,
+                overloaded: false,
+                restarts: 0,
+            })),
+        }
+    }
+
+    /// Returns the name of the controller.
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sets how long the controller may go without a successful reconcile before it is
+    /// unhealthy, such as when its resync period changes.
+    pub(crate) fn set_max_staleness(&self, max_staleness: Duration) {
+        self.state.lock().unwrap().max_staleness = max_staleness;
+    }
+
+    /// Records that the control loop has started.
+    pub(crate) fn started(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.running = true;
+        state.exit_error = None;
+        state.initialized = false;
+        state.started = Some(self.clock.now());
+        state.consecutive_failures = 0;
+        state.ready = false;
+        state.overloaded = false;
+    }
+
+    /// Records that the control loop has initialized the controller.
+    pub(crate) fn initialized(&self) {
+        self.state.lock().unwrap().initialized = true;
+    }
+
+    /// Records that the control loop has terminated.
+    pub(crate) fn stopped(&self) {
+        self.state.lock().unwrap().running = false;
+    }
+
+    /// Records that the control loop stopped on its own and won't be restarted, and why.
+    pub(crate) fn exited(&self, exit: String) {
+        self.state.lock().unwrap().exit_error = Some(exit);
+    }
+
+    /// Records that the control loop is being restarted after stopping on its own.
+    pub(crate) fn restarted(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.restarts = state.restarts.saturating_add(1);
+    }
+
+    /// Records that the controller has been paused or resumed.
+    pub(crate) fn set_paused(&self, paused: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.paused = paused;
+        if !paused {
+            state.started = Some(self.clock.now());
+        }
+    }
+
+    /// Records a successful reconcile, and how much work it did.
+    pub(crate) fn succeeded(&self, stats: ReconcileStats) {
+        let mut state = self.state.lock().unwrap();
+        state.last_success = Some((self.clock.now(), SystemTime::now()));
+        state.last_error = None;
+        state.consecutive_failures = 0;
+        state.last_pass = Some(stats);
+        state.ready = true;
+    }
+
+    /// Records whether the controller's reconciles are consistently overrunning its resync period.
+    pub(crate) fn set_overloaded(&self, overloaded: bool) {
+        self.state.lock().unwrap().overloaded = overloaded;
+    }
+
+    /// Records a failed attempt to initialize the controller. Unlike a failed reconcile, it
+    /// doesn't count towards the consecutive failures.
+    pub(crate) fn initialize_failed(&self, error: String) {
+        self.state.lock().unwrap().last_error = Some((error, SystemTime::now()));
+    }
+
+    /// Records a failed reconcile.
+    pub(crate) fn failed(&self, error: String) {
+        let mut state = self.state.lock().unwrap();
+        state.last_error = Some((error, SystemTime::now()));
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+    }
+
+    /// Takes a snapshot of the current health.
+    pub(crate) fn snapshot(&self) -> ControllerHealth {
+        let state = self.state.lock().unwrap();
+        let since = state.last_success.map(|(at, _)| at).max(state.started);
+        let now = self.clock.now();
+        let fresh =
+            since.is_some_and(|since| now.saturating_duration_since(since) <= state.max_staleness);
+
+        ControllerHealth {
+            name: self.name.to_string(),
+            running: state.running,
+            exit_error: state.exit_error.clone(),
+            state: match (state.running, state.initialized) {
+                (false, _) => LoopState::Stopped,
+                (true, false) => LoopState::Initializing,
+                (true, true) => LoopState::Running,
+            },
+            ready: state.running && state.ready,
+            paused: state.paused,
+            last_success: state.last_success.map(|(_, at)| at),
+            last_error: state.last_error.as_ref().map(|(error, _)| error.clone()),
+            last_error_at: state.last_error.as_ref().map(|(_, at)| *at),
+            consecutive_failures: state.consecutive_failures,
+            last_pass: state.last_pass,
+            overloaded: state.overloaded,
+            restarts: state.restarts,
+            healthy: state.running && (fresh || state.paused),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Randomizes resync periods so that controllers started together don't all tick in lockstep.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Configures how much each resync period is randomized by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JitterConfig {
+    /// The largest fraction of the period that each tick may be moved earlier or later by. A
+    /// fraction of 0.1 spreads ticks of a 10 second period between 9 and 11 seconds apart. Clamped
+    /// to between 0 and 1.
+    pub fraction: f64,
+    /// When set, the random delays are generated from this seed so that they are reproducible,
+    /// such as in tests. The seed is combined with the controller's name, so controllers sharing
+    /// a config still tick at different times. When unset, a random seed is used.
+    pub seed: Option<u64>,
+}
+
+impl JitterConfig {
+    /// Create a new JitterConfig which randomizes each period by up to the given fraction, using
+    /// a random seed.
+    pub fn new(fraction: f64) -> JitterConfig {
+        JitterConfig {
+            fraction,
+            seed: None,
+        }
+    }
+
+    /// Returns this config, generating its delays from the given seed.
+    pub fn with_seed(self, seed: u64) -> JitterConfig {
+        JitterConfig {
+            seed: Some(seed),
+            ..self
+        }
+    }
+}
+
+/// Applies a JitterConfig to the periods of a single controller.
+pub(crate) struct Jitter {
+    fraction: f64,
+    rng: SplitMix64,
+}
+
+impl Jitter {
+    /// Create a new Jitter for the named controller.
+    pub(crate) fn new(config: JitterConfig, name: &str) -> Jitter {
+        let seed = match config.seed {
+            Some(seed) => seed ^ fnv1a(name),
+            None => RandomState::new().build_hasher().finish(),
+        };
+        let fraction = if config.fraction.is_finite() {
+            config.fraction.clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        Jitter {
+            fraction,
+            rng: SplitMix64(seed),
+        }
+    }
+
+    /// Returns the given period moved earlier or later by a random amount of up to the configured
+    /// fraction of it.
+    pub(crate) fn apply(&mut self, period: Duration) -> Duration {
+        // Spread uniformly over [-fraction, fraction).
+        let offset = self.fraction * (2.0 * self.rng.next_f64() - 1.0);
+        period.mul_f64(1.0 + offset)
+    }
+}
+
+/// A small, fast pseudo random number generator. It is only used to spread load, so it has no
+/// need to be cryptographically secure.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Hashes a string the same way on every run and platform, unlike the std hashers.
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
@@ -0,0 +1,531 @@
+//! Supports controllers that reconcile one item at a time, rather than everything at once.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use tokio::sync::mpsc;
+use tracing::{debug, error, warn};
+
+use crate::builder::ControllerBuilder;
+use crate::context::ReconcileContext;
+use crate::controller::{Controller, Draining, ReconcileOutcome, SourceChain};
+use crate::controller_host::ControllerHost;
+use crate::events::{EventRecorder, EventType, LoggingEventRecorder};
+use crate::queue::WorkQueue;
+use crate::trigger::Trigger;
+
+/// Defines the methods a controller implements to reconcile the items it is responsible for one
+/// key at a time. Wrap it in `Keyed` to run it like any other Controller.
+#[async_trait]
+pub trait KeyedController: Send + Sync {
+    /// Identifies a single item this controller reconciles, such as a row id.
+    type Key: Eq + Hash + Clone + Debug + Send + Sync + 'static;
+
+    /// Provide an error type that this controller should return. See `Controller::Error`.
+    type Error: Error + 'static + Sync + Send;
+
+    /// Provide a human readable name for this controller. See `Controller::name`.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Provide the startup phase this controller belongs to. See `Controller::phase`.
+    fn phase(&self) -> u32 {
+        0
+    }
+
+    /// Provide initial setup for the given Controller if necessary. See `Controller::initialize`.
+    async fn initialize(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// List the keys of every item this controller is responsible for reconciling. Called on each
+    /// pass of the control loop.
+    async fn list_keys(&mut self) -> Result<Vec<Self::Key>, Self::Error>;
+
+    /// Reconcile the single item identified by the given key. This takes `&self`, so that several
+    /// keys can be reconciled at once with `Keyed::set_concurrency`; keep any state it needs to
+    /// change behind a lock or atomic of its own. For the same reason, every key reconciled in a
+    /// pass shares the pass's ReconcileContext.
+    async fn reconcile_key(
+        &self,
+        key: Self::Key,
+        ctx: &ReconcileContext,
+    ) -> Result<(), Self::Error>;
+
+    /// Provide the necessary logic to handle cleaning up soft deleted specs. See
+    /// `Controller::cleanup`.
+    async fn cleanup(&mut self, _ctx: &mut ReconcileContext) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// List the keys of every soft deleted item that is ready to be finalized and hard deleted.
+    /// Called on each cleanup pass, after `cleanup`. By default nothing is listed, so only
+    /// `cleanup` runs.
+    async fn list_deleted_keys(&mut self) -> Result<Vec<Self::Key>, Self::Error> {
+        Ok(Vec::new())
+    }
+
+    /// Run any teardown the soft deleted item identified by the given key needs before it is hard
+    /// deleted, such as releasing resources it owns elsewhere. The item is only passed to
+    /// `remove` once this succeeds, so a failed teardown is retried on the next cleanup pass.
+    async fn finalize(&mut self, _key: &Self::Key) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Hard delete the item identified by the given key, once it has been finalized. Controllers
+    /// that list deleted keys must implement this, or their items are finalized on every cleanup
+    /// pass.
+    async fn remove(&mut self, _key: &Self::Key) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Retrieve the resync period for this controller. See `Controller::resync_period`.
+    fn resync_period(&self) -> Duration;
+
+    /// Retrieve the cleanup period for this controller. See `Controller::cleanup_period`.
+    fn cleanup_period(&self) -> Duration {
+        self.resync_period()
+    }
+
+    /// Return whether this controller has anything to clean up. Return `false` only if `cleanup`
+    /// does nothing and no keys are ever deleted, so that `cleanup` and the deleted keys are never
+    /// looked at. See `Controller::has_cleanup`.
+    fn has_cleanup(&self) -> bool {
+        true
+    }
+
+    /// Check that this controller is configured sensibly. See `Controller::validate`.
+    fn validate(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Receive the EventRecorder this controller should record events to. See
+    /// `Controller::set_event_recorder`.
+    fn set_event_recorder(&mut self, _events: Arc<dyn EventRecorder>) {}
+}
+
+/// Adapts a KeyedController into a Controller. Each reconcile lists the controller's keys, adds
+/// them to a WorkQueue, and reconciles each queued key in turn.
+///
+/// A key that fails to reconcile is rate limited in the queue, and the control loop is asked to
+/// come back once its backoff has passed. A key that succeeds has its backoff reset.
+///
+/// With `set_max_retries`, a key that keeps failing is eventually given up on, so that a single
+/// poison item can't starve the rest. It is dead lettered: forgotten by the queue, and skipped by
+/// later passes for as long as `list_keys` keeps returning it.
+///
+/// By default keys are reconciled one at a time. With `set_concurrency`, several workers drain
+/// the queue at once, each reconciling one key at a time. The workers run concurrently on the
+/// control loop's task rather than in parallel, which suits IO bound work; CPU bound work should
+/// be moved off it, with `tokio::task::spawn_blocking` for example. Listing keys and cleaning up
+/// still happen one at a time.
+///
+/// Each cleanup runs the controller's `cleanup`, then finalizes every key from
+/// `list_deleted_keys`, hard deleting each with `remove` only once its `finalize` has succeeded.
+///
+/// Specific keys can be reconciled on demand through a KeyTrigger, from
+/// `ControllerHost::add_keyed_controller`, without waiting for the next resync or listing every
+/// key.
+///
+/// With `ExecutorConfig::drain_on_shutdown`, a control loop cancelled mid-reconcile lets each
+/// worker finish the key it holds, but take no more, leaving the rest queued for the next start.
+///
+/// Each key a pass reconciles is recorded as processed in its ReconcileContext, and each dead
+/// lettered key it passes over as skipped.
+///
+/// Every key that fails to reconcile or finalize, or is dead lettered, is recorded as a `Warning`
+/// event keyed by its Debug representation.
+pub struct Keyed<C: KeyedController> {
+    controller: C,
+    queue: WorkQueue<C::Key>,
+    /// How many times a failing key is retried before it is dead lettered, if ever.
+    max_retries: Option<u32>,
+    /// When set, receives every key that is dead lettered.
+    dead_letters: Option<mpsc::Sender<DeadLetter<C::Key, C::Error>>>,
+    /// The keys that have been dead lettered.
+    dead: HashSet<C::Key>,
+    /// How many keys are reconciled at once.
+    concurrency: usize,
+    /// Records an event for every key that fails. Shared with the controller.
+    events: Arc<dyn EventRecorder>,
+    /// Set once the control loop is draining, after which workers take no more keys.
+    draining: Draining,
+    /// The keys asked for through a KeyTrigger. Shared with every KeyTrigger.
+    targets: Arc<Targets<C::Key>>,
+}
+
+/// What has been asked for through a KeyTrigger since the last reconcile pass.
+struct Targets<K> {
+    /// The keys to reconcile.
+    keys: Mutex<Vec<K>>,
+    /// Set when a full pass, listing every key, was asked for.
+    full: AtomicBool,
+}
+
+/// A key that was given up on after failing more than `max_retries` times in a row.
+#[derive(Debug)]
+pub struct DeadLetter<K, E> {
+    /// The key that kept failing.
+    pub key: K,
+    /// The error from the key's final attempt.
+    pub last_error: E,
+}
+
+impl<C: KeyedController> Keyed<C> {
+    /// Create a new Keyed controller with its own WorkQueue.
+    pub fn new(controller: C) -> Keyed<C> {
+        Self::with_queue(controller, WorkQueue::new())
+    }
+
+    /// Create a new Keyed controller that reconciles keys from the given WorkQueue. Keys added to
+    /// the queue from elsewhere are reconciled on the next pass.
+    pub fn with_queue(controller: C, queue: WorkQueue<C::Key>) -> Keyed<C> {
+        Keyed {
+            controller,
+            queue,
+            max_retries: None,
+            dead_letters: None,
+            dead: HashSet::new(),
+            concurrency: 1,
+            events: Arc::new(LoggingEventRecorder),
+            draining: Draining::default(),
+            targets: Arc::new(Targets {
+                keys: Mutex::new(Vec::new()),
+                full: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Sets how many times in a row a key may fail to reconcile and be retried before it is dead
+    /// lettered. By default keys are retried forever.
+    pub fn set_max_retries(&mut self, max_retries: Option<u32>) {
+        self.max_retries = max_retries;
+    }
+
+    /// Sets a channel that is sent every key that is dead lettered, along with its last error. If
+    /// the channel is full, the dead letter is dropped rather than holding up reconciliation. It
+    /// is logged either way.
+    pub fn set_dead_letters(
+        &mut self,
+        dead_letters: Option<mpsc::Sender<DeadLetter<C::Key, C::Error>>>,
+    ) {
+        self.dead_letters = dead_letters;
+    }
+
+    /// Sets how many keys are reconciled at once. Defaults to 1, and 0 is treated as 1.
+    pub fn set_concurrency(&mut self, concurrency: usize) {
+        self.concurrency = concurrency.max(1);
+    }
+
+    /// Returns the WorkQueue this controller reconciles keys from.
+    pub fn queue(&self) -> &WorkQueue<C::Key> {
+        &self.queue
+    }
+}
+
+#[async_trait]
+impl<C: KeyedController> Controller for Keyed<C> {
+    type Error = C::Error;
+
+    fn name(&self) -> &str {
+        self.controller.name()
+    }
+
+    fn phase(&self) -> u32 {
+        self.controller.phase()
+    }
+
+    async fn initialize(&mut self) -> Result<(), Self::Error> {
+        self.controller.initialize().await
+    }
+
+    async fn reconcile(
+        &mut self,
+        ctx: &mut ReconcileContext,
+    ) -> Result<ReconcileOutcome, Self::Error> {
+        let targets = std::mem::take(&mut *self.targets.keys.lock().unwrap());
+        let full = self.targets.full.swap(false, Ordering::SeqCst);
+        // A pass that was only asked for to reconcile specific keys skips listing the rest. Should
+        // a resync tick take its place, the pass the KeyTrigger asked for is still pending, and
+        // lists every key right after.
+        if targets.is_empty() || full {
+            let keys = match self.controller.list_keys().await {
+                Ok(keys) => keys,
+                Err(e) => {
+                    // Put back what was asked for, so that the pass retrying this one still
+                    // reconciles those keys and lists every key if that was asked for.
+                    let mut keys = self.targets.keys.lock().unwrap();
+                    keys.splice(0..0, targets);
+                    if full {
+                        self.targets.full.store(true, Ordering::SeqCst);
+                    }
+                    return Err(e);
+                }
+            };
+            // Only remember dead lettered keys for as long as they are still being listed.
+            if !self.dead.is_empty() {
+                let listed: HashSet<&C::Key> = keys.iter().collect();
+                self.dead.retain(|key| listed.contains(key));
+            }
+            for key in keys {
+                // Rate limited keys are already due to be added back once their backoff has
+                // passed.
+                if !self.dead.contains(&key) && self.queue.num_requeues(&key) == 0 {
+                    self.queue.add(key);
+                }
+            }
+        }
+        // Keys asked for explicitly are retried right away, with a clean slate, even if they were
+        // backing off or dead lettered.
+        for key in targets {
+            self.dead.remove(&key);
+            self.queue.forget(&key);
+            self.queue.add(key);
+        }
+
+        // Only drain the keys that are ready now. Rate limited keys are picked up once their
+        // backoff has passed.
+        let ctx = &*ctx;
+        let failures = join_all((0..self.concurrency).map(|_| self.work(ctx))).await;
+
+        let mut retry_after: Option<Duration> = None;
+        for (key, e) in failures.into_iter().flatten() {
+            if self
+                .max_retries
+                .is_some_and(|max| self.queue.num_requeues(&key) >= max)
+            {
+                self.dead_letter(key, e);
+                continue;
+            }
+            let delay = self.queue.add_rate_limited(key);
+            retry_after = Some(retry_after.map_or(delay, |d| d.min(delay)));
+        }
+
+        Ok(match retry_after {
+            Some(delay) => ReconcileOutcome::RequeueAfter(delay),
+            None => ReconcileOutcome::Done,
+        })
+    }
+
+    async fn cleanup(&mut self, ctx: &mut ReconcileContext) -> Result<(), Self::Error> {
+        self.controller.cleanup(ctx).await?;
+
+        // Keep finalizing the rest when one key fails, so that a single stuck item doesn't hold
+        // up every other deletion. The first failure is still returned to be reported.
+        let mut first_error = None;
+        for key in self.controller.list_deleted_keys().await? {
+            let result = match self.controller.finalize(&key).await {
+                Ok(()) => self.controller.remove(&key).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                error!(
+                    controller = %self.controller.name(),
+                    key = ?key,
+                    error = %e,
+                    error.source = %SourceChain(&e),
+                    "controller finalize of key failed"
+                );
+                self.events.event(
+                    &format!("{:?}", key),
+                    "FinalizeFailed",
+                    &format!("finalize failed: {}", e),
+                    EventType::Warning,
+                );
+                first_error.get_or_insert(e);
+            }
+        }
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn resync_period(&self) -> Duration {
+        self.controller.resync_period()
+    }
+
+    fn cleanup_period(&self) -> Duration {
+        self.controller.cleanup_period()
+    }
+
+    fn validate(&self) -> Result<(), Self::Error> {
+        self.controller.validate()
+    }
+
+    fn has_cleanup(&self) -> bool {
+        self.controller.has_cleanup()
+    }
+
+    fn set_event_recorder(&mut self, events: Arc<dyn EventRecorder>) {
+        self.controller.set_event_recorder(events.clone());
+        self.events = events;
+    }
+
+    fn set_draining(&mut self, draining: Draining) {
+        self.draining = draining;
+    }
+}
+
+impl<C: KeyedController> Keyed<C> {
+    /// Reconciles keys from the queue until none are ready, or the control loop is draining,
+    /// returning the keys that failed along with their errors. Several of these run at once when
+    /// the concurrency is above 1.
+    async fn work(&self, ctx: &ReconcileContext) -> Vec<(C::Key, C::Error)> {
+        let mut failures = Vec::new();
+        while !self.draining.is_draining() {
+            let key = match self.queue.try_get() {
+                Some(key) => key,
+                None => break,
+            };
+            // Marks the key done even if reconciling it panics or is timed out.
+            let _processing = Processing {
+                queue: self.queue.clone(),
+                key: key.clone(),
+            };
+            if self.dead.contains(&key) {
+                ctx.record_skipped(1);
+                continue;
+            }
+            ctx.record_processed(1);
+            match self.controller.reconcile_key(key.clone(), ctx).await {
+                Ok(()) => self.queue.forget(&key),
+                Err(e) => {
+                    error!(
+                        controller = %self.controller.name(),
+                        key = ?key,
+                        error = %e,
+                        error.source = %SourceChain(&e),
+                        attempt = self.queue.num_requeues(&key) + 1,
+                        "controller reconcile of key failed"
+                    );
+                    self.events.event(
+                        &format!("{:?}", key),
+                        "ReconcileFailed",
+                        &format!("reconcile failed: {}", e),
+                        EventType::Warning,
+                    );
+                    failures.push((key, e));
+                }
+            }
+        }
+        failures
+    }
+
+    /// Gives up on a key that has failed too many times in a row.
+    fn dead_letter(&mut self, key: C::Key, last_error: C::Error) {
+        warn!(
+            controller = %self.controller.name(),
+            "giving up on key {:?} after {} retries", key, self.queue.num_requeues(&key)
+        );
+        self.events.event(
+            &format!("{:?}", key),
+            "DeadLettered",
+            &format!(
+                "giving up after {} retries: {}",
+                self.queue.num_requeues(&key),
+                last_error
+            ),
+            EventType::Warning,
+        );
+        self.queue.forget(&key);
+        self.dead.insert(key.clone());
+
+        if let Some(dead_letters) = &self.dead_letters {
+            if dead_letters
+                .try_send(DeadLetter { key, last_error })
+                .is_err()
+            {
+                debug!(controller = %self.controller.name(), "dead letter channel full or closed, dropping dead letter");
+            }
+        }
+    }
+}
+
+/// Marks a key handed out by a WorkQueue as done when dropped.
+struct Processing<K>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+{
+    queue: WorkQueue<K>,
+    key: K,
+}
+
+impl<K> Drop for Processing<K>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+{
+    fn drop(&mut self) {
+        self.queue.done(&self.key);
+    }
+}
+
+/// A Trigger for a Keyed controller, which can also ask for a specific key to be reconciled, such
+/// as when a user asks to retry an item. Returned by `ControllerHost::add_keyed_controller`.
+/// Clones share the same controller.
+pub struct KeyTrigger<K> {
+    targets: Arc<Targets<K>>,
+    trigger: Trigger,
+}
+
+impl<K> KeyTrigger<K> {
+    /// Asks for the given key to be reconciled as soon as possible, without listing every other
+    /// key. The key is retried right away even if it was backing off after failing, or had been
+    /// dead lettered. Keys asked for while a pass is pending are reconciled by that same pass.
+    pub fn enqueue(&self, key: K) {
+        self.targets.keys.lock().unwrap().push(key);
+        self.trigger.trigger();
+    }
+
+    /// Asks for a full reconcile pass, listing every key, as soon as possible. Use this rather
+    /// than a plain Trigger for the same controller, which can be mistaken for a request for just
+    /// the keys asked for with `enqueue` when they coincide.
+    pub fn trigger(&self) {
+        self.targets.full.store(true, Ordering::SeqCst);
+        self.trigger.trigger();
+    }
+}
+
+impl<K> Clone for KeyTrigger<K> {
+    fn clone(&self) -> Self {
+        KeyTrigger {
+            targets: self.targets.clone(),
+            trigger: self.trigger.clone(),
+        }
+    }
+}
+
+impl<K> Debug for KeyTrigger<K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyTrigger").finish_non_exhaustive()
+    }
+}
+
+impl<E> ControllerHost<E>
+where
+    E: Error + Send + Sync + 'static,
+{
+    /// Adds a Keyed controller to the host, like `add_controller`, returning a KeyTrigger that can
+    /// reconcile specific keys on demand, as well as every key like a plain Trigger.
+    pub async fn add_keyed_controller<C>(
+        &mut self,
+        controller: impl Into<ControllerBuilder<Keyed<C>>>,
+    ) -> Result<KeyTrigger<C::Key>, E>
+    where
+        C: KeyedController<Error = E> + 'static,
+    {
+        let builder = controller.into();
+        let targets = builder.controller().targets.clone();
+        let trigger = self.add_controller(builder).await?;
+        Ok(KeyTrigger { targets, trigger })
+    }
+}
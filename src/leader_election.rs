@@ -0,0 +1,324 @@
+//! Leader election, so that when several replicas of a reconciler run for high availability only
+//! one of them reconciles at a time.
+//!
+//! A LeaderElector periodically tries to acquire, or renew, a named lease held in a LeaseStore.
+//! Whoever holds an unexpired lease is the leader. Pair it with
+//! `ControllerHost::run_while_leader` to only run controllers while this replica is the leader.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::{
+    sync::watch,
+    task::JoinHandle,
+    time::{interval, sleep_until, timeout, timeout_at, Instant, MissedTickBehavior},
+};
+use tokio_context::context::Context;
+use tracing::{error, info};
+
+use crate::controller::SourceChain;
+
+/// The error type returned by a LeaseStore.
+pub type LeaseError = Box<dyn Error + Send + Sync>;
+
+/// Defines the storage that leases are held in, such as a row in a database table.
+#[async_trait]
+pub trait LeaseStore: Send + Sync {
+    /// Acquires the named lease for the given holder if it is free or has expired, or renews it
+    /// if the holder already holds it. The lease is held for `duration` from now. Returns whether
+    /// the holder now holds the lease.
+    async fn try_acquire(
+        &self,
+        lease: &str,
+        holder: &str,
+        duration: Duration,
+    ) -> Result<bool, LeaseError>;
+
+    /// Gives up the named lease if the given holder holds it, so another replica can take over
+    /// without waiting for it to expire.
+    async fn release(&self, lease: &str, holder: &str) -> Result<(), LeaseError>;
+}
+
+/// Configures a LeaderElector.
+#[derive(Debug, Clone)]
+pub struct LeaderElectionConfig {
+    /// The name of the lease that replicas compete for.
+    pub lease_name: String,
+    /// Uniquely identifies this replica as the holder of the lease, e.g. a hostname.
+    pub holder: String,
+    /// How long an acquired lease is held before it expires if it isn't renewed.
+    pub lease_duration: Duration,
+    /// How often the lease is renewed, or acquisition is retried. This should be comfortably
+    /// shorter than `lease_duration`, so the leader renews well before its lease expires, and
+    /// must be shorter than it and non-zero.
+    pub renew_interval: Duration,
+}
+
+impl LeaderElectionConfig {
+    /// Create a new LeaderElectionConfig with a 15 second lease renewed every 5 seconds.
+    pub fn new(lease_name: impl Into<String>, holder: impl Into<String>) -> LeaderElectionConfig {
+        LeaderElectionConfig {
+            lease_name: lease_name.into(),
+            holder: holder.into(),
+            lease_duration: Duration::from_secs(15),
+            renew_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Competes for leadership by periodically acquiring or renewing a lease.
+pub struct LeaderElector {
+    store: Arc<dyn LeaseStore>,
+    config: LeaderElectionConfig,
+}
+
+impl LeaderElector {
+    /// Create a new LeaderElector competing for a lease in the given store.
+    ///
+    /// Returns an error if the renew interval is zero, or isn't shorter than the lease duration,
+    /// in which case the lease would expire between every renewal.
+    pub fn new(
+        store: Arc<dyn LeaseStore>,
+        config: LeaderElectionConfig,
+    ) -> Result<LeaderElector, InvalidLeaderElection> {
+        let reason = if config.renew_interval.is_zero() {
+            Some("the renew interval is zero".to_string())
+        } else if config.renew_interval >= config.lease_duration {
+            Some(format!(
+                "the renew interval of {:?} isn't shorter than the lease duration of {:?}",
+                config.renew_interval, config.lease_duration
+            ))
+        } else {
+            None
+        };
+        match reason {
+            Some(reason) => Err(InvalidLeaderElection {
+                lease: config.lease_name,
+                reason,
+            }),
+            None => Ok(LeaderElector { store, config }),
+        }
+    }
+
+    /// Begins competing for leadership, returning a receiver that holds whether this replica is
+    /// currently the leader, along with the handle of the task renewing the lease. Cancel the
+    /// given context to stop competing, which also releases the lease if it is held.
+    ///
+    /// Leadership is given up as soon as a renewal fails, rather than waiting for the lease to
+    /// expire, so that two replicas never both believe they are the leader. A renewal that is
+    /// still waiting on the store when the lease would expire counts as failed, and so does
+    /// leadership outliving the lease between renewals. The lease is taken to expire
+    /// `lease_duration` after the renewal that last succeeded began, as the store may have
+    /// renewed it any time after that.
+    pub fn start(&self, mut ctx: Context) -> (watch::Receiver<bool>, JoinHandle<()>) {
+        let (tx, rx) = watch::channel(false);
+        let store = self.store.clone();
+        let config = self.config.clone();
+
+        let handle = tokio::task::spawn(async move {
+            let mut interval = interval(config.renew_interval);
+            // Renewals that were missed waiting on the store aren't worth catching up on.
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // When the lease expires, while this replica holds it.
+            let mut expires_at: Option<Instant> = None;
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {},
+                    _ = sleep_until(expires_at.unwrap_or_else(Instant::now)), if expires_at.is_some() => {
+                        error!(lease = %config.lease_name, "Lease expired before it was renewed");
+                        expires_at = None;
+                        set_leader(&tx, &config.lease_name, false);
+                        continue;
+                    }
+                    _ = ctx.done() => break,
+                }
+
+                let started = Instant::now();
+                let limit = expires_at.map_or(config.lease_duration, |at| {
+                    at.saturating_duration_since(started)
+                });
+                let attempt = timeout(
+                    limit,
+                    store.try_acquire(&config.lease_name, &config.holder, config.lease_duration),
+                );
+                let result = tokio::select! {
+                    result = attempt => result,
+                    _ = ctx.done() => break,
+                };
+                let leader = match result {
+                    Ok(Ok(leader)) => leader,
+                    Ok(Err(e)) => {
+                        error!(
+                            lease = %config.lease_name,
+                            error = %e,
+                            error.source = %SourceChain(&*e),
+                            "failed to renew lease"
+                        );
+                        false
+                    }
+                    Err(_) => {
+                        error!(lease = %config.lease_name, "renewing lease timed out after {:?}", limit);
+                        false
+                    }
+                };
+                expires_at = leader.then(|| started + config.lease_duration);
+                set_leader(&tx, &config.lease_name, leader);
+            }
+
+            if let Some(expires_at) = expires_at {
+                let _ = tx.send(false);
+                // Waiting any longer than the lease has left is pointless, as it expires anyway.
+                match timeout_at(
+                    expires_at,
+                    store.release(&config.lease_name, &config.holder),
+                )
+                .await
+                {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => {
+                        error!(
+                            lease = %config.lease_name,
+                            error = %e,
+                            error.source = %SourceChain(&*e),
+                            "failed to release lease"
+                        );
+                    }
+                    Err(_) => {
+                        error!(lease = %config.lease_name, "releasing lease timed out, leaving it to expire");
+                    }
+                }
+            }
+        });
+
+        (rx, handle)
+    }
+}
+
+/// Publishes whether this replica is the leader, logging when that changes.
+fn set_leader(tx: &watch::Sender<bool>, lease: &str, leader: bool) {
+    if leader == *tx.borrow() {
+        return;
+    }
+    if leader {
+        info!(lease = %lease, "Acquired leadership");
+    } else {
+        info!(lease = %lease, "Lost leadership");
+    }
+    let _ = tx.send(leader);
+}
+
+/// A LeaderElectionConfig that a LeaderElector can't compete for leadership with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidLeaderElection {
+    lease: String,
+    reason: String,
+}
+
+impl fmt::Display for InvalidLeaderElection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid leader election for lease {}: {}",
+            self.lease, self.reason
+        )
+    }
+}
+
+impl Error for InvalidLeaderElection {}
+
+#[cfg(feature = "mysql")]
+pub use self::mysql::MySqlLeaseStore;
+
+#[cfg(feature = "mysql")]
+mod mysql {
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use sqlx::{MySql, Pool};
+
+    use super::{LeaseError, LeaseStore};
+
+    /// A LeaseStore that holds leases as rows in a MySQL table, which must have the following
+    /// schema. The table name is configurable.
+    ///
+    /// ```sql
+    /// CREATE TABLE IF NOT EXISTS leader_lease (
+    ///     name VARCHAR(256) NOT NULL,
+    ///     holder VARCHAR(256) NOT NULL,
+    ///     expires_at datetime(6) NOT NULL,
+    ///     PRIMARY KEY (name)
+    /// );
+    /// ```
+    pub struct MySqlLeaseStore {
+        pool: Pool<MySql>,
+        acquire_query: String,
+        holder_query: String,
+        release_query: String,
+    }
+
+    impl MySqlLeaseStore {
+        /// Create a new MySqlLeaseStore keeping leases in the `leader_lease` table.
+        pub fn new(pool: Pool<MySql>) -> MySqlLeaseStore {
+            Self::with_table(pool, "leader_lease")
+        }
+
+        /// Create a new MySqlLeaseStore keeping leases in the given table.
+        pub fn with_table(pool: Pool<MySql>, table: &str) -> MySqlLeaseStore {
+            MySqlLeaseStore {
+                pool,
+                // MySQL applies these assignments in order, so `holder` must be updated first for
+                // `expires_at` to see the new holder when an expired lease changes hands.
+                acquire_query: format!(
+                    "INSERT INTO {table} (name, holder, expires_at) \
+                     VALUES (?, ?, NOW(6) + INTERVAL ? MICROSECOND) \
+                     ON DUPLICATE KEY UPDATE \
+                     holder = IF(holder = VALUES(holder) OR expires_at < NOW(6), VALUES(holder), holder), \
+                     expires_at = IF(holder = VALUES(holder), VALUES(expires_at), expires_at)",
+                    table = table
+                ),
+                holder_query: format!("SELECT holder FROM {} WHERE name = ?", table),
+                release_query: format!(
+                    "UPDATE {} SET expires_at = NOW(6) WHERE name = ? AND holder = ?",
+                    table
+                ),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl LeaseStore for MySqlLeaseStore {
+        async fn try_acquire(
+            &self,
+            lease: &str,
+            holder: &str,
+            duration: Duration,
+        ) -> Result<bool, LeaseError> {
+            sqlx::query(&self.acquire_query)
+                .bind(lease)
+                .bind(holder)
+                .bind(duration.as_micros() as u64)
+                .execute(&self.pool)
+                .await?;
+
+            let (current,): (String,) = sqlx::query_as(&self.holder_query)
+                .bind(lease)
+                .fetch_one(&self.pool)
+                .await?;
+
+            Ok(current == holder)
+        }
+
+        async fn release(&self, lease: &str, holder: &str) -> Result<(), LeaseError> {
+            sqlx::query(&self.release_query)
+                .bind(lease)
+                .bind(holder)
+                .execute(&self.pool)
+                .await?;
+
+            Ok(())
+        }
+    }
+}
@@ -1,2 +1,36 @@
+#[cfg(feature = "admin")]
+pub mod admin;
+pub mod backoff;
+pub mod boxed;
+pub mod builder;
+pub mod clock;
+pub mod context;
 pub mod controller;
 pub mod controller_host;
+pub mod cron;
+pub mod each;
+pub mod events;
+pub mod health;
+pub mod jitter;
+pub mod keyed;
+pub mod leader_election;
+#[cfg(feature = "logging")]
+pub mod logging;
+pub mod metrics;
+pub mod owners;
+pub mod queue;
+pub mod rate_limit;
+pub mod signals;
+pub mod status;
+pub mod store;
+pub mod supervision;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod trigger;
+
+/// Items the code generated by `#[derive(Controller)]` refers to. Not part of the public API.
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub mod __private {
+    pub use async_trait::async_trait;
+}
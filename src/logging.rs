@@ -0,0 +1,112 @@
+//! Sets up `tracing` output suited to a controller host, for binaries that don't bring their own
+//! subscriber. Enabled by the `logging` feature.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::fmt::time::SystemTime;
+use tracing_subscriber::util::{SubscriberInitExt, TryInitError};
+use tracing_subscriber::EnvFilter;
+
+/// How much is logged, from only errors up to everything. Parses from its lowercase name, such
+/// as `"info"`, so that it can be read from a flag or environment variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevel {
+    /// Only errors, such as failed reconciles.
+    Error,
+    /// Errors and warnings, such as restarted control loops and dead lettered keys.
+    Warn,
+    /// Warnings and above, plus lifecycle events such as control loops starting and stopping.
+    Info,
+    /// Info and above, plus detail on each pass, such as how long it took.
+    Debug,
+    /// Everything, including the finest detail from dependencies.
+    Trace,
+}
+
+impl LogLevel {
+    /// Returns the lowercase name of the level, as it is parsed.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = LogLevelError;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level.trim().to_ascii_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warn" | "warning" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err(LogLevelError {
+                level: level.to_string(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A log level that could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLevelError {
+    level: String,
+}
+
+impl fmt::Display for LogLevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid log level {:?}: expected one of error, warn, info, debug or trace",
+            self.level
+        )
+    }
+}
+
+impl Error for LogLevelError {}
+
+/// Installs a global `tracing` subscriber that logs everything at the given level and above to
+/// stdout, with RFC 3339 UTC timestamps. Each line includes the fields of the spans it was logged
+/// in, so that logs from a reconcile pass carry the controller's name and the iteration.
+///
+/// A `RUST_LOG` environment variable, in the `EnvFilter` syntax, takes precedence over the given
+/// level, so that one controller or module can be turned up without a redeploy, as in
+/// `RUST_LOG=info,reconciliation::controller=debug`.
+///
+/// Returns an error if a global subscriber was already installed.
+pub fn init_tracing(level: LogLevel) -> Result<(), TryInitError> {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::default().add_directive(LevelFilter::from(level).into()));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_timer(SystemTime)
+        .finish()
+        .try_init()
+}
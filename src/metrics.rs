@@ -0,0 +1,33 @@
+//! A hook for exporting reconcile counts and latencies to a metrics system of your choosing.
+
+use std::time::Duration;
+
+use crate::context::ReconcileStats;
+
+/// Receives a measurement for each call the control loop makes into a controller. Implement this
+/// to export metrics, for example into a Prometheus registry, without this crate depending on
+/// any particular metrics library.
+pub trait ControllerMetrics: Send + Sync {
+    /// Records a single call to `reconcile` on the named controller, how long it took, and
+    /// whether it succeeded.
+    fn record_reconcile(&self, name: &str, duration: Duration, result: Result<(), ()>);
+
+    /// Records a single call to `cleanup` on the named controller, how long it took, and whether
+    /// it succeeded.
+    fn record_cleanup(&self, name: &str, duration: Duration, result: Result<(), ()>);
+
+    /// Records how many items a single call to `reconcile` on the named controller processed and
+    /// skipped, as the controller reported them to its ReconcileContext. Recorded whether or not
+    /// the call succeeded. By default it is discarded.
+    fn record_reconcile_items(&self, _name: &str, _stats: ReconcileStats) {}
+}
+
+/// A ControllerMetrics that discards every measurement. Used when no metrics are configured.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopMetrics;
+
+impl ControllerMetrics for NoopMetrics {
+    fn record_reconcile(&self, _name: &str, _duration: Duration, _result: Result<(), ()>) {}
+
+    fn record_cleanup(&self, _name: &str, _duration: Duration, _result: Result<(), ()>) {}
+}
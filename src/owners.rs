@@ -0,0 +1,120 @@
+//! Lets an object reconciled by one controller own objects reconciled by others, so that deleting
+//! the owner cascades to what it owns through the controllers, rather than through database
+//! foreign keys.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::queue::WorkQueue;
+use crate::trigger::Trigger;
+
+/// Identifies the owner of an object, by the kind of object it is, such as the table it is kept
+/// in, and its key. Objects store the OwnerRef of their owner, so that their controller can find
+/// them once the owner is deleted.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct OwnerRef {
+    /// The kind of object the owner is. Dependents watch for deletions of a kind with
+    /// `Owners::watch`.
+    pub kind: String,
+    /// The key of the owner, as a string so that owners of any kind can be referred to alike.
+    pub key: String,
+}
+
+impl OwnerRef {
+    /// Create a new OwnerRef to the object of the given kind with the given key.
+    pub fn new(kind: impl Into<String>, key: impl ToString) -> OwnerRef {
+        OwnerRef {
+            kind: kind.into(),
+            key: key.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for OwnerRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.kind, self.key)
+    }
+}
+
+/// Carries the deletion of owners to the controllers reconciling the objects they own. Clones
+/// share the same dependents, so hand one to every controller involved.
+///
+/// The controller of the objects that are owned calls `watch` for the kind of their owner, and
+/// cleans up the objects owned by each OwnerRef it is sent, marking each `done` on the queue, or
+/// requeueing it with `add_rate_limited` if that fails. The owner's controller calls `deleted`
+/// once it soft deletes or removes an owner. A Trigger set with `wake` reconciles a dependent
+/// right away, rather than on its next resync.
+///
+/// Deletions are only carried in memory. One that hasn't been handled yet when the process stops
+/// is lost, so dependents that must not be orphaned should also look for objects whose owner no
+/// longer exists now and then, such as on each resync.
+#[derive(Clone, Default)]
+pub struct Owners {
+    kinds: Arc<Mutex<HashMap<String, Dependents>>>,
+}
+
+/// Everyone watching for deletions of a single kind of owner.
+#[derive(Default)]
+struct Dependents {
+    queues: Vec<WorkQueue<OwnerRef>>,
+    triggers: Vec<Trigger>,
+}
+
+impl Owners {
+    /// Create a new Owners without any dependents.
+    pub fn new() -> Owners {
+        Owners::default()
+    }
+
+    /// Returns a queue that is sent every owner of the given kind that is deleted from now on.
+    /// Shut the queue down to stop watching.
+    pub fn watch(&self, kind: &str) -> WorkQueue<OwnerRef> {
+        let queue = WorkQueue::new();
+        let mut kinds = self.kinds.lock().unwrap();
+        kinds
+            .entry(kind.to_string())
+            .or_default()
+            .queues
+            .push(queue.clone());
+        queue
+    }
+
+    /// Fires the given Trigger whenever an owner of the given kind is deleted, such as the one
+    /// `ControllerHost::add_controller` returns for the controller watching it.
+    pub fn wake(&self, kind: &str, trigger: Trigger) {
+        let mut kinds = self.kinds.lock().unwrap();
+        kinds
+            .entry(kind.to_string())
+            .or_default()
+            .triggers
+            .push(trigger);
+    }
+
+    /// Records that the given owner was deleted, queueing it for every dependent watching its
+    /// kind, and waking them. Deleting the same owner again before it is processed queues it
+    /// only once.
+    pub fn deleted(&self, owner: &OwnerRef) {
+        let mut kinds = self.kinds.lock().unwrap();
+        let dependents = match kinds.get_mut(&owner.kind) {
+            Some(dependents) => dependents,
+            None => return,
+        };
+        dependents.queues.retain(|queue| !queue.is_shutting_down());
+        for queue in dependents.queues.iter() {
+            queue.add(owner.clone());
+        }
+        for trigger in dependents.triggers.iter() {
+            trigger.trigger();
+        }
+    }
+}
+
+impl fmt::Debug for Owners {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kinds = self.kinds.lock().unwrap();
+        let mut watched: Vec<&String> = kinds.keys().collect();
+        watched.sort();
+        f.debug_struct("Owners").field("kinds", &watched).finish()
+    }
+}
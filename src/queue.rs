@@ -0,0 +1,235 @@
+//! A work queue of keys to reconcile, modeled on client-go's rate limiting work queue.
+//!
+//! Keys are de-duplicated while they wait in the queue, and a key that is re-added while it is
+//! being processed is held back until processing is marked `done`, so the same key is never
+//! handed out twice at once.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+use crate::backoff::BackoffConfig;
+use crate::clock::{Clock, TokioClock};
+use crate::rate_limit::{ExponentialRateLimiter, RateLimiter};
+
+/// A cloneable work queue of keys. Clones share the same underlying queue.
+pub struct WorkQueue<K> {
+    inner: Arc<Inner<K>>,
+}
+
+struct Inner<K> {
+    state: Mutex<State<K>>,
+    /// Notified whenever a key becomes available, or the queue is shut down.
+    notify: Notify,
+    /// Used to delay keys added by `add_rate_limited`.
+    limiter: Box<dyn RateLimiter<K>>,
+    /// The clock that delays of keys added by `add_after` are waited out on.
+    clock: Arc<dyn Clock>,
+}
+
+struct State<K> {
+    /// Keys waiting to be handed out by `get`, in order.
+    queue: VecDeque<K>,
+    /// Keys that need processing. This is everything in `queue`, plus keys that were re-added
+    /// while being processed.
+    dirty: HashSet<K>,
+    /// Keys that have been handed out by `get` but not yet marked `done`.
+    processing: HashSet<K>,
+    /// How many times each key has been rate limited since it was last forgotten.
+    requeues: HashMap<K, u32>,
+    shutting_down: bool,
+}
+
+impl<K> Clone for WorkQueue<K> {
+    fn clone(&self) -> Self {
+        WorkQueue {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K> WorkQueue<K>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+{
+    /// Create a new WorkQueue which rate limits keys using the default backoff.
+    pub fn new() -> WorkQueue<K> {
+        Self::with_backoff(BackoffConfig::default())
+    }
+
+    /// Create a new WorkQueue which rate limits keys using the given backoff. Each key backs off
+    /// independently of the others.
+    pub fn with_backoff(backoff: BackoffConfig) -> WorkQueue<K> {
+        Self::with_rate_limiter(ExponentialRateLimiter::new(backoff))
+    }
+
+    /// Create a new WorkQueue which rate limits keys using the given rate limiter, such as a
+    /// MaxOfRateLimiter that caps how fast keys are retried overall as well as backing each one
+    /// off.
+    pub fn with_rate_limiter(limiter: impl RateLimiter<K> + 'static) -> WorkQueue<K> {
+        Self::with_clock(limiter, Arc::new(TokioClock))
+    }
+
+    /// Create a new WorkQueue which rate limits keys using the given rate limiter, waiting out
+    /// their delays on the given clock. The queue doesn't share the clock in the ExecutorConfig of
+    /// the controller it feeds, so to test a Keyed controller with a ManualClock, give the same
+    /// clock to its queue, and to any BucketRateLimiter, as well.
+    pub fn with_clock(
+        limiter: impl RateLimiter<K> + 'static,
+        clock: Arc<dyn Clock>,
+    ) -> WorkQueue<K> {
+        WorkQueue {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State {
+                    queue: VecDeque::new(),
+                    dirty: HashSet::new(),
+                    processing: HashSet::new(),
+                    requeues: HashMap::new(),
+                    shutting_down: false,
+                }),
+                notify: Notify::new(),
+                limiter: Box::new(limiter),
+                clock,
+            }),
+        }
+    }
+
+    /// Adds a key to the queue. Does nothing if the key is already waiting in the queue. If the
+    /// key is currently being processed, it is queued again once it is marked `done`.
+    pub fn add(&self, key: K) {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.shutting_down || !state.dirty.insert(key.clone()) {
+            return;
+        }
+
+        if !state.processing.contains(&key) {
+            state.queue.push_back(key);
+            self.inner.notify.notify_one();
+        }
+    }
+
+    /// Adds a key to the queue once the given delay has passed.
+    pub fn add_after(&self, key: K, delay: Duration) {
+        if delay == Duration::from_secs(0) {
+            self.add(key);
+            return;
+        }
+
+        let queue = self.clone();
+        let sleep = self.inner.clock.sleep(delay);
+        tokio::spawn(async move {
+            sleep.await;
+            queue.add(key);
+        });
+    }
+
+    /// Adds a key to the queue after the delay the queue's rate limiter gives it, which by
+    /// default grows exponentially with each consecutive call for the same key. Call `forget`
+    /// once the key has been processed successfully to reset its delay. Returns the delay the key
+    /// was added with.
+    pub fn add_rate_limited(&self, key: K) -> Duration {
+        let requeues = {
+            let mut state = self.inner.state.lock().unwrap();
+            let requeues = state.requeues.entry(key.clone()).or_insert(0);
+            *requeues = requeues.saturating_add(1);
+            *requeues
+        };
+        let delay = self.inner.limiter.when_ready(&key, requeues);
+        self.add_after(key, delay);
+        delay
+    }
+
+    /// Stops rate limiting the given key, so that its next `add_rate_limited` uses the base
+    /// delay again.
+    pub fn forget(&self, key: &K) {
+        self.inner.state.lock().unwrap().requeues.remove(key);
+        self.inner.limiter.forget(key);
+    }
+
+    /// Returns how many times the given key has been rate limited since it was last forgotten.
+    pub fn num_requeues(&self, key: &K) -> u32 {
+        let state = self.inner.state.lock().unwrap();
+        state.requeues.get(key).copied().unwrap_or(0)
+    }
+
+    /// Waits for the next key to process. The key must be marked `done` once it has been
+    /// processed. Returns `None` once the queue has been shut down and drained.
+    pub async fn get(&self) -> Option<K> {
+        loop {
+            // Created before checking the state so that a notification sent in between isn't
+            // missed.
+            let notified = self.inner.notify.notified();
+            {
+                let mut state = self.inner.state.lock().unwrap();
+                if let Some(key) = Self::pop(&mut state) {
+                    return Some(key);
+                }
+
+                if state.shutting_down {
+                    return None;
+                }
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Returns the next key to process if one is waiting, without waiting for one otherwise. The
+    /// key must be marked `done` once it has been processed, as with `get`.
+    pub fn try_get(&self) -> Option<K> {
+        Self::pop(&mut self.inner.state.lock().unwrap())
+    }
+
+    /// Hands out the key at the front of the queue, if any.
+    fn pop(state: &mut State<K>) -> Option<K> {
+        let key = state.queue.pop_front()?;
+        state.dirty.remove(&key);
+        state.processing.insert(key.clone());
+        Some(key)
+    }
+
+    /// Marks a key handed out by `get` as processed. If the key was added again while it was
+    /// being processed, it is put back on the queue.
+    pub fn done(&self, key: &K) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.processing.remove(key);
+        if state.dirty.contains(key) {
+            state.queue.push_back(key.clone());
+            self.inner.notify.notify_one();
+        }
+    }
+
+    /// Returns the number of keys waiting to be handed out.
+    pub fn len(&self) -> usize {
+        self.inner.state.lock().unwrap().queue.len()
+    }
+
+    /// Returns whether no keys are waiting to be handed out.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Shuts the queue down. Further adds are ignored, and `get` returns `None` once the keys
+    /// already queued have been handed out.
+    pub fn shut_down(&self) {
+        self.inner.state.lock().unwrap().shutting_down = true;
+        self.inner.notify.notify_waiters();
+    }
+
+    /// Returns whether the queue has been shut down.
+    pub fn is_shutting_down(&self) -> bool {
+        self.inner.state.lock().unwrap().shutting_down
+    }
+}
+
+impl<K> Default for WorkQueue<K>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
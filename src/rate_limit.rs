@@ -0,0 +1,150 @@
+//! Rate limiters that decide how long a key added to a WorkQueue with `add_rate_limited` waits
+//! before it is queued, modeled on client-go's work queue rate limiters.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::backoff::BackoffConfig;
+use crate::clock::{Clock, TokioClock};
+
+/// Decides how long a key that is being retried waits before it is handed out again. A WorkQueue
+/// counts how many times in a row each key has been rate limited, and asks its RateLimiter for
+/// the delay each time.
+pub trait RateLimiter<K>: Send + Sync {
+    /// Returns how long the given key should wait before it is queued again, given how many times
+    /// in a row it has now been rate limited, counting this time.
+    fn when_ready(&self, key: &K, requeues: u32) -> Duration;
+
+    /// Called once the given key has been forgotten, after being processed successfully, so that
+    /// a limiter tracking keys itself can drop it. By default it does nothing.
+    fn forget(&self, _key: &K) {}
+}
+
+/// Delays each key by a backoff that grows exponentially with how many times in a row it has been
+/// rate limited, independently of every other key. This is what a WorkQueue uses by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ExponentialRateLimiter {
+    backoff: BackoffConfig,
+}
+
+impl ExponentialRateLimiter {
+    /// Create a new ExponentialRateLimiter which delays keys according to the given backoff.
+    pub fn new(backoff: BackoffConfig) -> ExponentialRateLimiter {
+        ExponentialRateLimiter { backoff }
+    }
+}
+
+impl<K> RateLimiter<K> for ExponentialRateLimiter {
+    fn when_ready(&self, _key: &K, requeues: u32) -> Duration {
+        self.backoff.delay(requeues)
+    }
+}
+
+/// Limits how often keys are retried across every key together, with a token bucket. The bucket
+/// holds up to `burst` tokens, refilled at `per_second` tokens a second, and each retry takes
+/// one. A retry that finds the bucket empty is delayed until a token would be free for it, so
+/// however many keys fail at once, they are retried no faster than the refill rate.
+///
+/// Pair it with an ExponentialRateLimiter in a MaxOfRateLimiter to put a ceiling on the retries
+/// of a queue whose keys each back off on their own.
+pub struct BucketRateLimiter {
+    per_second: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// The tokens in the bucket as of `updated`. Negative once retries have been delayed waiting
+    /// for tokens that haven't been refilled yet.
+    tokens: f64,
+    updated: Option<Instant>,
+}
+
+impl BucketRateLimiter {
+    /// Create a new BucketRateLimiter, which starts with a full bucket of `burst` tokens, refilled
+    /// at `per_second` tokens a second. A rate that isn't positive is raised to one token a
+    /// second, and a burst of zero to one.
+    pub fn new(per_second: f64, burst: u32) -> BucketRateLimiter {
+        Self::with_clock(per_second, burst, Arc::new(TokioClock))
+    }
+
+    /// Create a new BucketRateLimiter like `new`, refilling its bucket as time passes on the
+    /// given clock.
+    pub fn with_clock(per_second: f64, burst: u32, clock: Arc<dyn Clock>) -> BucketRateLimiter {
+        let burst = f64::from(burst.max(1));
+        BucketRateLimiter {
+            per_second: if per_second > 0.0 { per_second } else { 1.0 },
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                updated: None,
+            }),
+            clock,
+        }
+    }
+}
+
+impl fmt::Debug for BucketRateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BucketRateLimiter")
+            .field("per_second", &self.per_second)
+            .field("burst", &self.burst)
+            .field("bucket", &self.bucket)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K> RateLimiter<K> for BucketRateLimiter {
+    fn when_ready(&self, _key: &K, _requeues: u32) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = self.clock.now();
+        if let Some(updated) = bucket.updated {
+            let refilled = now.saturating_duration_since(updated).as_secs_f64() * self.per_second;
+            bucket.tokens = (bucket.tokens + refilled).min(self.burst);
+        }
+        bucket.updated = Some(now);
+
+        // Taking a token that isn't there yet reserves the next one to be refilled.
+        bucket.tokens -= 1.0;
+        if bucket.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            // A rate slow enough to wait longer than a Duration can hold waits as long as it can.
+            Duration::try_from_secs_f64(-bucket.tokens / self.per_second).unwrap_or(Duration::MAX)
+        }
+    }
+}
+
+/// Delays each key by the longest delay any of its rate limiters give it.
+pub struct MaxOfRateLimiter<K> {
+    limiters: Vec<Box<dyn RateLimiter<K>>>,
+}
+
+impl<K> MaxOfRateLimiter<K> {
+    /// Create a new MaxOfRateLimiter combining the given rate limiters. Every one of them is
+    /// asked for each key, so that a BucketRateLimiter among them counts every retry.
+    pub fn new(limiters: Vec<Box<dyn RateLimiter<K>>>) -> MaxOfRateLimiter<K> {
+        MaxOfRateLimiter { limiters }
+    }
+}
+
+impl<K> RateLimiter<K> for MaxOfRateLimiter<K> {
+    fn when_ready(&self, key: &K, requeues: u32) -> Duration {
+        self.limiters
+            .iter()
+            .map(|limiter| limiter.when_ready(key, requeues))
+            .max()
+            .unwrap_or_default()
+    }
+
+    fn forget(&self, key: &K) {
+        for limiter in &self.limiters {
+            limiter.forget(key);
+        }
+    }
+}
@@ -0,0 +1,32 @@
+//! Waits for the signals an orchestrator sends to stop a process, so that binaries don't each
+//! have to translate them into a shutdown themselves.
+
+use std::io;
+
+use tracing::info;
+
+/// Waits until the process is asked to shut down, which on Unix is a SIGTERM or SIGINT.
+///
+/// Returns an error if the signal handlers could not be installed.
+#[cfg(unix)]
+pub async fn shutdown() -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = sigterm.recv() => info!("Received SIGTERM, shutting down"),
+        _ = sigint.recv() => info!("Received SIGINT, shutting down"),
+    }
+    Ok(())
+}
+
+/// Waits until the process is asked to shut down, which on platforms other than Unix is a ctrl-c.
+///
+/// Returns an error if the signal handler could not be installed.
+#[cfg(not(unix))]
+pub async fn shutdown() -> io::Result<()> {
+    tokio::signal::ctrl_c().await?;
+    info!("Received ctrl-c, shutting down");
+    Ok(())
+}
@@ -0,0 +1,212 @@
+//! Supports controllers that only compute the status each spec should have, leaving listing specs
+//! and writing back the statuses that changed to the crate.
+
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tracing::error;
+
+use crate::context::ReconcileContext;
+use crate::controller::{Controller, Draining, ReconcileOutcome, SourceChain};
+use crate::events::EventRecorder;
+use crate::store::Store;
+
+/// Defines the methods a controller implements to reconcile each spec by computing its status.
+/// Wrap it in `StatusReconciler`, along with the Store its specs are kept in, to run it like any
+/// other Controller. Controllers that need more control over reconciling should implement
+/// Controller directly instead.
+#[async_trait]
+pub trait StatusController: Send + Sync {
+    /// The items this controller reconciles, as kept in its Store, each holding its spec along
+    /// with its current status, if it has one.
+    type Spec: Send + Sync;
+
+    /// The status computed for each spec. Compared with the spec's current status, so that only
+    /// statuses that changed are written back.
+    type Status: PartialEq + Send + Sync;
+
+    /// Provide an error type that this controller should return. See `Controller::Error`.
+    type Error: Error + 'static + Sync + Send;
+
+    /// Provide a human readable name for this controller. See `Controller::name`.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Provide the startup phase this controller belongs to. See `Controller::phase`.
+    fn phase(&self) -> u32 {
+        0
+    }
+
+    /// Provide initial setup for the given Controller if necessary. See `Controller::initialize`.
+    async fn initialize(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Return the status the given spec currently has, if any.
+    fn status<'a>(&self, spec: &'a Self::Spec) -> Option<&'a Self::Status>;
+
+    /// Compute the status the given spec should have. This should only compute the status,
+    /// without side effects, as it is written back by `StatusReconciler` only if it differs from
+    /// the current one. Every spec in a pass shares the pass's ReconcileContext.
+    fn reconcile_one(
+        &self,
+        spec: &Self::Spec,
+        ctx: &ReconcileContext,
+    ) -> Result<Self::Status, Self::Error>;
+
+    /// Set the given status on the spec, ready to be written back to the Store.
+    fn set_status(&self, spec: &mut Self::Spec, status: Self::Status);
+
+    /// Provide the necessary logic to handle cleaning up soft deleted specs. See
+    /// `Controller::cleanup`.
+    async fn cleanup(&mut self, _ctx: &mut ReconcileContext) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Retrieve the resync period for this controller. See `Controller::resync_period`.
+    fn resync_period(&self) -> Duration;
+
+    /// Retrieve the cleanup period for this controller. See `Controller::cleanup_period`.
+    fn cleanup_period(&self) -> Duration {
+        self.resync_period()
+    }
+
+    /// Return whether this controller has anything to clean up. See `Controller::has_cleanup`.
+    fn has_cleanup(&self) -> bool {
+        true
+    }
+
+    /// Check that this controller is configured sensibly. See `Controller::validate`.
+    fn validate(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Receive the EventRecorder this controller should record events to. See
+    /// `Controller::set_event_recorder`.
+    fn set_event_recorder(&mut self, _events: Arc<dyn EventRecorder>) {}
+
+    /// Receive the signal that tells this controller the control loop is draining. See
+    /// `Controller::set_draining`.
+    fn set_draining(&mut self, _draining: Draining) {}
+}
+
+/// Adapts a StatusController into a Controller. Each reconcile lists every spec in the Store,
+/// computes the status each should have with `reconcile_one`, and writes back the specs whose
+/// status changed with a single `upsert_many`.
+///
+/// Each spec whose status changed, or couldn't be computed, is recorded as processed in the
+/// pass's ReconcileContext, and each whose status was already up to date as skipped.
+///
+/// A spec whose status can't be computed is logged and skipped, so that it doesn't hold up the
+/// rest, and the first such error fails the reconcile once the other changes have been written.
+///
+/// The Store is only used to reconcile. Controllers whose `cleanup` needs it too can be handed a
+/// clone, as stores are usually cheap to clone, such as those wrapping a connection pool.
+pub struct StatusReconciler<C, S> {
+    controller: C,
+    store: S,
+}
+
+impl<C, S> StatusReconciler<C, S>
+where
+    C: StatusController,
+    S: Store<C::Spec>,
+{
+    /// Create a new StatusReconciler that reconciles the specs kept in the given Store.
+    pub fn new(controller: C, store: S) -> StatusReconciler<C, S> {
+        StatusReconciler { controller, store }
+    }
+}
+
+#[async_trait]
+impl<C, S> Controller for StatusReconciler<C, S>
+where
+    C: StatusController,
+    S: Store<C::Spec>,
+    C::Error: From<S::Error>,
+{
+    type Error = C::Error;
+
+    fn name(&self) -> &str {
+        self.controller.name()
+    }
+
+    fn phase(&self) -> u32 {
+        self.controller.phase()
+    }
+
+    async fn initialize(&mut self) -> Result<(), Self::Error> {
+        self.controller.initialize().await
+    }
+
+    async fn reconcile(
+        &mut self,
+        ctx: &mut ReconcileContext,
+    ) -> Result<ReconcileOutcome, Self::Error> {
+        let mut changed = Vec::new();
+        let mut first_error = None;
+        for mut spec in self.store.all().await? {
+            match self.controller.reconcile_one(&spec, ctx) {
+                Ok(status) => {
+                    if self.controller.status(&spec) == Some(&status) {
+                        ctx.record_skipped(1);
+                    } else {
+                        ctx.record_processed(1);
+                        self.controller.set_status(&mut spec, status);
+                        changed.push(spec);
+                    }
+                }
+                Err(e) => {
+                    ctx.record_processed(1);
+                    error!(
+                        controller = %self.controller.name(),
+                        error = %e,
+                        error.source = %SourceChain(&e),
+                        "controller failed to compute a status"
+                    );
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        if !changed.is_empty() {
+            self.store.upsert_many(&changed).await?;
+        }
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(ReconcileOutcome::Done),
+        }
+    }
+
+    async fn cleanup(&mut self, ctx: &mut ReconcileContext) -> Result<(), Self::Error> {
+        self.controller.cleanup(ctx).await
+    }
+
+    fn resync_period(&self) -> Duration {
+        self.controller.resync_period()
+    }
+
+    fn cleanup_period(&self) -> Duration {
+        self.controller.cleanup_period()
+    }
+
+    fn validate(&self) -> Result<(), Self::Error> {
+        self.controller.validate()
+    }
+
+    fn has_cleanup(&self) -> bool {
+        self.controller.has_cleanup()
+    }
+
+    fn set_event_recorder(&mut self, events: Arc<dyn EventRecorder>) {
+        self.controller.set_event_recorder(events);
+    }
+
+    fn set_draining(&mut self, draining: Draining) {
+        self.controller.set_draining(draining);
+    }
+}
@@ -0,0 +1,182 @@
+//! A storage abstraction, so that controllers can be written generically over where the specs
+//! they reconcile are kept.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tracing::error;
+
+use crate::controller::SourceChain;
+
+/// Defines the methods a repository of `T`s implements so that a controller can reconcile them
+/// without depending on a particular database. Items are soft deleted by marking them deleted,
+/// and hard deleted by `remove` once they have been soft deleted for long enough.
+#[async_trait]
+pub trait Store<T: Send + Sync>: Send + Sync {
+    /// Identifies a single item in the store, such as a row id.
+    type Key: Send + Sync;
+
+    /// Provide an error type that this store should return.
+    type Error: Error + 'static + Sync + Send;
+
+    /// Fetch every item that hasn't been soft deleted.
+    async fn all(&self) -> Result<Vec<T>, Self::Error>;
+
+    /// Fetch every item that has been soft deleted for longer than the given policy retains it,
+    /// which is ready to be removed. The store decides which class each item belongs to, such as
+    /// by the state it was left in, and items without a class are kept for the policy's default
+    /// retention.
+    async fn all_deleted(&self, retention: &RetentionPolicy) -> Result<Vec<T>, Self::Error>;
+
+    /// Fetch the item with the given key, if there is one.
+    async fn get(&self, key: &Self::Key) -> Result<Option<T>, Self::Error>;
+
+    /// Insert the given item, or update it if it already exists, returning its key.
+    async fn upsert(&self, item: &T) -> Result<Self::Key, Self::Error>;
+
+    /// Hard delete the item with the given key. Removing an item that doesn't exist is not an
+    /// error, so that cleanup can safely be retried.
+    async fn remove(&self, key: &Self::Key) -> Result<(), Self::Error>;
+
+    /// Insert or update each of the given items, returning their keys in the same order. By
+    /// default this upserts them one at a time; stores that can write many items in one round
+    /// trip should override it.
+    async fn upsert_many(&self, items: &[T]) -> Result<Vec<Self::Key>, Self::Error> {
+        let mut keys = Vec::with_capacity(items.len());
+        for item in items {
+            keys.push(self.upsert(item).await?);
+        }
+        Ok(keys)
+    }
+
+    /// Hard delete every item with one of the given keys, with the same semantics as `remove`.
+    /// By default this removes them one at a time; stores that can delete many items in one
+    /// round trip should override it.
+    async fn remove_many(&self, keys: &[Self::Key]) -> Result<(), Self::Error> {
+        for key in keys {
+            self.remove(key).await?;
+        }
+        Ok(())
+    }
+}
+
+/// A Store whose reads and writes can be grouped into a Transaction, so that a reconcile pass
+/// computes statuses from the same state it writes them back over, rather than from reads that
+/// another writer may have changed in between.
+///
+/// A reconcile begins a transaction, reads and writes through it, as it is a Store itself, and
+/// hands the pass's result to `Transaction::finish`, which commits it if the pass succeeded and
+/// rolls it back if it failed:
+///
+/// ```ignore
+/// let tx = self.store.begin().await?;
+/// let result = self.reconcile_in(&tx, ctx).await;
+/// tx.finish(result).await
+/// ```
+#[async_trait]
+pub trait TransactionalStore<T: Send + Sync>: Store<T> {
+    /// A transaction on this store, which reads and writes items within it.
+    type Transaction: Store<T, Key = Self::Key, Error = Self::Error>
+        + Transaction<Error = Self::Error>;
+
+    /// Begin a new transaction.
+    async fn begin(&self) -> Result<Self::Transaction, Self::Error>;
+}
+
+/// A transaction begun with `TransactionalStore::begin`. Nothing written through it is seen by
+/// anyone else until it is committed, and all of it is discarded if it is rolled back, or dropped
+/// without being committed.
+#[async_trait]
+pub trait Transaction: Send + Sized {
+    /// Provide an error type that this transaction should return.
+    type Error: Error + 'static + Sync + Send;
+
+    /// Commit everything written through the transaction.
+    async fn commit(self) -> Result<(), Self::Error>;
+
+    /// Discard everything written through the transaction.
+    async fn rollback(self) -> Result<(), Self::Error>;
+
+    /// Commits the transaction if the given result of the work done within it is `Ok`, returning
+    /// the result unless the commit fails. Otherwise rolls the transaction back and returns the
+    /// error, logging the rollback's own failure if it has one, as the original error is the one
+    /// worth reporting.
+    async fn finish<R, E>(self, result: Result<R, E>) -> Result<R, E>
+    where
+        R: Send,
+        E: From<Self::Error> + Send,
+    {
+        match result {
+            Ok(value) => {
+                self.commit().await?;
+                Ok(value)
+            }
+            Err(e) => {
+                if let Err(rollback) = self.rollback().await {
+                    error!(
+                        error = %rollback,
+                        error.source = %SourceChain(&rollback),
+                        "failed to roll back transaction"
+                    );
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+/// How long soft deleted items are kept before they are hard deleted. Items can be kept for
+/// different lengths of time depending on which class they belong to, so that for example items
+/// left in a failed state stick around for longer to be investigated.
+///
+/// A policy converts from a Duration, which keeps every item for that long.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    default: Duration,
+    classes: BTreeMap<String, Duration>,
+}
+
+impl RetentionPolicy {
+    /// Create a new RetentionPolicy that keeps every item for the given duration.
+    pub fn new(default: Duration) -> RetentionPolicy {
+        RetentionPolicy {
+            default,
+            classes: BTreeMap::new(),
+        }
+    }
+
+    /// Keeps items of the given class for the given duration, rather than the default.
+    pub fn with_class(mut self, class: impl Into<String>, retention: Duration) -> Self {
+        self.classes.insert(class.into(), retention);
+        self
+    }
+
+    /// Returns how long items without a class of their own are kept.
+    pub fn default_retention(&self) -> Duration {
+        self.default
+    }
+
+    /// Returns how long items of the given class are kept, falling back to the default for
+    /// classes without a retention of their own.
+    pub fn retention(&self, class: Option<&str>) -> Duration {
+        class
+            .and_then(|class| self.classes.get(class))
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    /// Returns each class with a retention of its own, in order of the class names.
+    pub fn classes(&self) -> impl Iterator<Item = (&str, Duration)> {
+        self.classes
+            .iter()
+            .map(|(class, retention)| (class.as_str(), *retention))
+    }
+}
+
+impl From<Duration> for RetentionPolicy {
+    fn from(default: Duration) -> Self {
+        RetentionPolicy::new(default)
+    }
+}
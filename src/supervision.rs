@@ -0,0 +1,66 @@
+//! Restarts control loops that stop on their own, rather than leaving them dead.
+
+use crate::backoff::BackoffConfig;
+use crate::controller::ControllerExit;
+
+/// Which of the ways a control loop can stop on its own it is restarted after. A control loop
+/// that was cancelled is never restarted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Restart {
+    /// Leave the control loop stopped.
+    #[default]
+    Never,
+    /// Restart the control loop whether it gave up after too many consecutive failures, or
+    /// panicked.
+    Always,
+    /// Only restart the control loop if it panicked.
+    OnPanic,
+}
+
+/// Describes whether a controller's control loop is restarted after it stops on its own, and how
+/// often. Set it with `ExecutorConfig::supervision`, or per controller with
+/// `ControllerBuilder::supervision`.
+///
+/// While it is restarted, the control loop's exit isn't reported to anyone waiting on it. Only once
+/// it is left stopped, because the policy doesn't restart it or it has run out of restarts, does
+/// `ControllerExecutor::wait` return.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SupervisionPolicy {
+    /// Which exits the control loop is restarted after.
+    pub restart: Restart,
+    /// The number of times the control loop may be restarted each time it is started, if
+    /// limited.
+    pub max_restarts: Option<u32>,
+    /// The delay before each restart, which grows with every restart.
+    pub backoff: BackoffConfig,
+}
+
+impl SupervisionPolicy {
+    /// Create a new SupervisionPolicy.
+    pub fn new(
+        restart: Restart,
+        max_restarts: Option<u32>,
+        backoff: BackoffConfig,
+    ) -> SupervisionPolicy {
+        SupervisionPolicy {
+            restart,
+            max_restarts,
+            backoff,
+        }
+    }
+
+    /// Returns whether a control loop that has already been restarted `restarts` times should be
+    /// restarted after exiting the given way.
+    pub(crate) fn should_restart<E>(&self, exit: &ControllerExit<E>, restarts: u32) -> bool {
+        if self.max_restarts.is_some_and(|max| restarts >= max) {
+            return false;
+        }
+        match (self.restart, exit) {
+            (_, ControllerExit::Cancelled) => false,
+            (Restart::Never, _) => false,
+            (Restart::Always, _) => true,
+            (Restart::OnPanic, ControllerExit::Panicked(_)) => true,
+            (Restart::OnPanic, _) => false,
+        }
+    }
+}
@@ -0,0 +1,398 @@
+//! Helpers for testing controllers without a database or metrics system. Enabled by the `testing`
+//! feature.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::time::Instant;
+
+use crate::clock::{Clock, TokioClock};
+use crate::context::ReconcileStats;
+use crate::metrics::ControllerMetrics;
+use crate::store::{RetentionPolicy, Store, Transaction, TransactionalStore};
+
+/// A Store that keeps its items in memory, for unit testing reconcile logic deterministically.
+/// Clones share the same items, so a test can hand one clone to a controller and keep another to
+/// seed data and assert on the resulting state.
+///
+/// Items are soft deleted with `soft_delete`, which hides them from `all` and, once they have
+/// been deleted for long enough, returns them from `all_deleted`. Deletion times are kept on
+/// tokio's clock, so they follow a paused clock in tests, or on the clock given to `with_clock`,
+/// such as the ManualClock a controller under test runs on. Items are classified for a
+/// RetentionPolicy with the function given to `with_classifier`, if any.
+///
+/// Transactions begun with `begin` buffer their writes, which are only applied to the store's
+/// items once committed. They don't isolate reads, so a transaction sees its own writes on top
+/// of the store's current items, including ones written by others since it began.
+pub struct InMemoryStore<K, T> {
+    key_of: Arc<dyn Fn(&T) -> K + Send + Sync>,
+    classify: Option<Classifier<T>>,
+    items: Arc<Mutex<HashMap<K, Entry<T>>>>,
+    clock: Arc<dyn Clock>,
+}
+
+/// Gets the class of an item, for a RetentionPolicy.
+type Classifier<T> = Arc<dyn Fn(&T) -> Option<String> + Send + Sync>;
+
+struct Entry<T> {
+    item: T,
+    /// When the item was soft deleted, if it has been.
+    deleted_at: Option<Instant>,
+}
+
+impl<K, T> Clone for InMemoryStore<K, T> {
+    fn clone(&self) -> Self {
+        InMemoryStore {
+            key_of: self.key_of.clone(),
+            classify: self.classify.clone(),
+            items: self.items.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+}
+
+impl<K, T> InMemoryStore<K, T>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    T: Clone + Send + Sync,
+{
+    /// Create a new, empty InMemoryStore, which uses the given function to get the key of each
+    /// item.
+    pub fn new(key_of: impl Fn(&T) -> K + Send + Sync + 'static) -> InMemoryStore<K, T> {
+        InMemoryStore {
+            key_of: Arc::new(key_of),
+            classify: None,
+            items: Arc::new(Mutex::new(HashMap::new())),
+            clock: Arc::new(TokioClock),
+        }
+    }
+
+    /// Keeps deletion times on the given clock, so that soft deleted items expire as it advances.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Uses the given function to get the class of each item, which decides how long it is kept
+    /// once soft deleted. Without one, every item is kept for the policy's default retention.
+    pub fn with_classifier(
+        mut self,
+        classify: impl Fn(&T) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.classify = Some(Arc::new(classify));
+        self
+    }
+
+    /// Inserts the given items, replacing any that already exist.
+    pub fn seed(&self, items: impl IntoIterator<Item = T>) {
+        let mut entries = self.items.lock().unwrap();
+        for item in items {
+            entries.insert(
+                (self.key_of)(&item),
+                Entry {
+                    item,
+                    deleted_at: None,
+                },
+            );
+        }
+    }
+
+    /// Soft deletes the item with the given key. Returns whether there was such an item.
+    pub fn soft_delete(&self, key: &K) -> bool {
+        match self.items.lock().unwrap().get_mut(key) {
+            Some(entry) => {
+                entry.deleted_at.get_or_insert_with(|| self.clock.now());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns whether the item with the given key has been soft deleted.
+    pub fn is_deleted(&self, key: &K) -> bool {
+        let entries = self.items.lock().unwrap();
+        entries.get(key).is_some_and(|e| e.deleted_at.is_some())
+    }
+
+    /// Returns a copy of every item in the store, including soft deleted ones, so that its state
+    /// can be compared between reconcile passes.
+    pub fn snapshot(&self) -> HashMap<K, T> {
+        let entries = self.items.lock().unwrap();
+        entries
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.item.clone()))
+            .collect()
+    }
+
+    /// Returns the number of items in the store, including soft deleted ones.
+    pub fn len(&self) -> usize {
+        self.items.lock().unwrap().len()
+    }
+
+    /// Returns whether the store has no items.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns whether the given item, soft deleted at the given time if at all, has been deleted
+    /// for longer than the policy retains it.
+    fn expired(&self, item: &T, deleted_at: Option<Instant>, retention: &RetentionPolicy) -> bool {
+        let class = self.classify.as_ref().and_then(|classify| classify(item));
+        let age = retention.retention(class.as_deref());
+        let now = self.clock.now();
+        deleted_at.is_some_and(|at| now.saturating_duration_since(at) > age)
+    }
+}
+
+#[async_trait]
+impl<K, T> Store<T> for InMemoryStore<K, T>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    T: Clone + Send + Sync,
+{
+    type Key = K;
+    type Error = Infallible;
+
+    async fn all(&self) -> Result<Vec<T>, Self::Error> {
+        let entries = self.items.lock().unwrap();
+        Ok(entries
+            .values()
+            .filter(|e| e.deleted_at.is_none())
+            .map(|e| e.item.clone())
+            .collect())
+    }
+
+    async fn all_deleted(&self, retention: &RetentionPolicy) -> Result<Vec<T>, Self::Error> {
+        let entries = self.items.lock().unwrap();
+        Ok(entries
+            .values()
+            .filter(|e| self.expired(&e.item, e.deleted_at, retention))
+            .map(|e| e.item.clone())
+            .collect())
+    }
+
+    async fn get(&self, key: &K) -> Result<Option<T>, Self::Error> {
+        let entries = self.items.lock().unwrap();
+        Ok(entries.get(key).map(|e| e.item.clone()))
+    }
+
+    async fn upsert(&self, item: &T) -> Result<K, Self::Error> {
+        let key = (self.key_of)(item);
+        let mut entries = self.items.lock().unwrap();
+        // Updating an item leaves it soft deleted if it was.
+        let deleted_at = entries.get(&key).and_then(|e| e.deleted_at);
+        entries.insert(
+            key.clone(),
+            Entry {
+                item: item.clone(),
+                deleted_at,
+            },
+        );
+        Ok(key)
+    }
+
+    async fn remove(&self, key: &K) -> Result<(), Self::Error> {
+        self.items.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<K, T> TransactionalStore<T> for InMemoryStore<K, T>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    T: Clone + Send + Sync,
+{
+    type Transaction = InMemoryTransaction<K, T>;
+
+    async fn begin(&self) -> Result<Self::Transaction, Self::Error> {
+        Ok(InMemoryTransaction {
+            store: self.clone(),
+            writes: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+/// A transaction on an InMemoryStore, which buffers its writes until it is committed. See
+/// `InMemoryStore`.
+pub struct InMemoryTransaction<K, T> {
+    store: InMemoryStore<K, T>,
+    /// The items written through the transaction by key, or None for those it removed.
+    writes: Mutex<HashMap<K, Option<T>>>,
+}
+
+impl<K, T> InMemoryTransaction<K, T>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    T: Clone + Send + Sync,
+{
+    /// Returns every item the transaction sees, along with when it was soft deleted, if it was.
+    fn view(&self) -> Vec<(Option<Instant>, T)> {
+        let entries = self.store.items.lock().unwrap();
+        let writes = self.writes.lock().unwrap();
+        let mut items: Vec<_> = entries
+            .iter()
+            .filter_map(|(key, entry)| match writes.get(key) {
+                Some(Some(item)) => Some((entry.deleted_at, item.clone())),
+                Some(None) => None,
+                None => Some((entry.deleted_at, entry.item.clone())),
+            })
+            .collect();
+        items.extend(
+            writes
+                .iter()
+                .filter(|(key, _)| !entries.contains_key(key))
+                .filter_map(|(_, item)| item.clone().map(|item| (None, item))),
+        );
+        items
+    }
+}
+
+#[async_trait]
+impl<K, T> Store<T> for InMemoryTransaction<K, T>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    T: Clone + Send + Sync,
+{
+    type Key = K;
+    type Error = Infallible;
+
+    async fn all(&self) -> Result<Vec<T>, Self::Error> {
+        Ok(self
+            .view()
+            .into_iter()
+            .filter(|(deleted_at, _)| deleted_at.is_none())
+            .map(|(_, item)| item)
+            .collect())
+    }
+
+    async fn all_deleted(&self, retention: &RetentionPolicy) -> Result<Vec<T>, Self::Error> {
+        Ok(self
+            .view()
+            .into_iter()
+            .filter(|(deleted_at, item)| self.store.expired(item, *deleted_at, retention))
+            .map(|(_, item)| item)
+            .collect())
+    }
+
+    async fn get(&self, key: &K) -> Result<Option<T>, Self::Error> {
+        if let Some(write) = self.writes.lock().unwrap().get(key) {
+            return Ok(write.clone());
+        }
+        self.store.get(key).await
+    }
+
+    async fn upsert(&self, item: &T) -> Result<K, Self::Error> {
+        let key = (self.store.key_of)(item);
+        self.writes
+            .lock()
+            .unwrap()
+            .insert(key.clone(), Some(item.clone()));
+        Ok(key)
+    }
+
+    async fn remove(&self, key: &K) -> Result<(), Self::Error> {
+        self.writes.lock().unwrap().insert(key.clone(), None);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<K, T> Transaction for InMemoryTransaction<K, T>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    T: Clone + Send + Sync,
+{
+    type Error = Infallible;
+
+    async fn commit(self) -> Result<(), Self::Error> {
+        let writes = self.writes.into_inner().unwrap();
+        // Apply every write under one lock, so that no one sees only some of them.
+        let mut entries = self.store.items.lock().unwrap();
+        for (key, write) in writes {
+            match write {
+                Some(item) => {
+                    // Updating an item leaves it soft deleted if it was.
+                    let deleted_at = entries.get(&key).and_then(|e| e.deleted_at);
+                    entries.insert(key, Entry { item, deleted_at });
+                }
+                None => {
+                    entries.remove(&key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn rollback(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// A ControllerMetrics that keeps every measurement in memory, by controller, so that a test can
+/// drive a control loop for a few ticks and assert on what it recorded. Share one, in an Arc,
+/// between `ExecutorConfig::metrics` and the test.
+#[derive(Debug, Default)]
+pub struct InMemoryMetrics {
+    recorded: Mutex<HashMap<String, RecordedMetrics>>,
+}
+
+/// The measurements an InMemoryMetrics recorded for a single controller.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecordedMetrics {
+    /// How long each call to `reconcile` took, in the order they were made.
+    pub reconciles: Vec<Duration>,
+    /// The number of calls to `reconcile` that failed.
+    pub reconcile_errors: u64,
+    /// How long each call to `cleanup` took, in the order they were made.
+    pub cleanups: Vec<Duration>,
+    /// The number of calls to `cleanup` that failed.
+    pub cleanup_errors: u64,
+    /// The items every call to `reconcile` processed and skipped, added up.
+    pub items: ReconcileStats,
+}
+
+impl InMemoryMetrics {
+    /// Create a new InMemoryMetrics with nothing recorded.
+    pub fn new() -> InMemoryMetrics {
+        InMemoryMetrics::default()
+    }
+
+    /// Returns a copy of everything recorded for the named controller so far, which is empty if
+    /// nothing has been.
+    pub fn recorded(&self, name: &str) -> RecordedMetrics {
+        let recorded = self.recorded.lock().unwrap();
+        recorded.get(name).cloned().unwrap_or_default()
+    }
+}
+
+impl ControllerMetrics for InMemoryMetrics {
+    fn record_reconcile(&self, name: &str, duration: Duration, result: Result<(), ()>) {
+        let mut recorded = self.recorded.lock().unwrap();
+        let metrics = recorded.entry(name.to_string()).or_default();
+        metrics.reconciles.push(duration);
+        if result.is_err() {
+            metrics.reconcile_errors += 1;
+        }
+    }
+
+    fn record_cleanup(&self, name: &str, duration: Duration, result: Result<(), ()>) {
+        let mut recorded = self.recorded.lock().unwrap();
+        let metrics = recorded.entry(name.to_string()).or_default();
+        metrics.cleanups.push(duration);
+        if result.is_err() {
+            metrics.cleanup_errors += 1;
+        }
+    }
+
+    fn record_reconcile_items(&self, name: &str, stats: ReconcileStats) {
+        let mut recorded = self.recorded.lock().unwrap();
+        let items = &mut recorded.entry(name.to_string()).or_default().items;
+        items.processed += stats.processed;
+        items.skipped += stats.skipped;
+    }
+}
@@ -0,0 +1,28 @@
+//! Lets application code nudge a controller into reconciling before its next resync tick.
+
+use tokio::sync::mpsc;
+
+/// A cloneable handle that asks a controller's control loop to run a reconcile pass as soon as
+/// possible, rather than waiting for the next resync tick.
+///
+/// Triggers are coalesced: firing several while a pass is already pending or underway results in
+/// a single follow-up pass. With `ExecutorConfig::trigger_debounce` set, so are those fired within
+/// the debounce window that starts with the first.
+#[derive(Debug, Clone)]
+pub struct Trigger(mpsc::Sender<()>);
+
+impl Trigger {
+    /// Create a new Trigger along with the receiving end the control loop waits on. The channel
+    /// only ever holds a single pending trigger, which is what coalesces them.
+    pub(crate) fn channel() -> (Trigger, mpsc::Receiver<()>) {
+        let (tx, rx) = mpsc::channel(1);
+        (Trigger(tx), rx)
+    }
+
+    /// Requests a reconcile pass. Returns immediately, whether or not a pass was already pending.
+    pub fn trigger(&self) {
+        // A full channel means a pass is already pending, and a closed one means the executor is
+        // gone. Either way there is nothing more to do.
+        let _ = self.0.try_send(());
+    }
+}